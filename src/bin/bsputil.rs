@@ -0,0 +1,147 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use quake::bsp::{parse_entities, Bsp, BspWriter, MapMesh};
+use quake::palette::convert_palette;
+
+const USAGE: &str = "Usage (--lightmaps bakes the lightmaps into the glTF):
+    bsputil extract-entities <bsp> [ent]
+    bsputil replace-entities <bsp> <ent> [output bsp]
+    bsputil export-obj <bsp> <obj> [palette.lmp]
+    bsputil export-glb <bsp> <glb> [palette.lmp] [--lightmaps]";
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["extract-entities", bsp] => extract_entities(bsp, &ent_path(bsp)),
+        ["extract-entities", bsp, ent] => extract_entities(bsp, ent),
+        ["replace-entities", bsp, ent] => replace_entities(bsp, ent, bsp),
+        ["replace-entities", bsp, ent, output] => replace_entities(bsp, ent, output),
+        ["export-obj", .., "--lightmaps"] => Err(
+            "OBJ has a single set of texture coordinates, use export-glb for lightmaps".to_string(),
+        ),
+        ["export-obj", bsp, obj] => export_obj(bsp, obj, None),
+        ["export-obj", bsp, obj, palette] => export_obj(bsp, obj, Some(palette)),
+        ["export-glb", bsp, glb, "--lightmaps"] => export_glb(bsp, glb, None, true),
+        ["export-glb", bsp, glb, palette, "--lightmaps"] => {
+            export_glb(bsp, glb, Some(palette), true)
+        }
+        ["export-glb", bsp, glb] => export_glb(bsp, glb, None, false),
+        ["export-glb", bsp, glb, palette] => export_glb(bsp, glb, Some(palette), false),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn open(filepath: &str) -> Result<Bsp, String> {
+    let data = fs::read(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+    Bsp::load(data).map_err(|e| format!("{}: {}", filepath, e))
+}
+
+/// "maps/e1m1.bsp" gives "maps/e1m1.ent", whatever the case of the extension
+fn ent_path(bsp: &str) -> String {
+    Path::new(bsp)
+        .with_extension("ent")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Writes the entity lump to a .ent file
+fn extract_entities(filepath: &str, ent: &str) -> Result<(), String> {
+    let bsp = open(filepath)?;
+    let text = bsp
+        .read_entities_text(&bsp.read_header())
+        .map_err(|e| format!("{}: {}", filepath, e))?;
+    fs::write(ent, text).map_err(|e| format!("{}: {}", ent, e))?;
+    println!("{}", ent);
+    Ok(())
+}
+
+/// Replaces the entity lump with a .ent file, which must parse
+fn replace_entities(filepath: &str, ent: &str, output: &str) -> Result<(), String> {
+    let bsp = open(filepath)?;
+    let text = fs::read_to_string(ent).map_err(|e| format!("{}: {}", ent, e))?;
+    let entities = parse_entities(&text).map_err(|e| format!("{}: {}", ent, e))?;
+
+    let mut writer = BspWriter::from_bsp(&bsp);
+    writer.set_entities_text(&text);
+    writer
+        .save(output)
+        .map_err(|e| format!("{}: {}", output, e))?;
+    println!("{}: {} entities", output, entities.len());
+    Ok(())
+}
+
+/// Reads the palette the textures of the mesh need
+fn read_palette(mesh: &MapMesh, palette: Option<&str>) -> Result<Vec<(u8, u8, u8)>, String> {
+    let palette = match palette {
+        Some(palette) => {
+            convert_palette(&fs::read(palette).map_err(|e| format!("{}: {}", palette, e))?)
+        }
+        None => Vec::new(),
+    };
+    // Half-Life textures bring their own
+    let needs_palette = mesh
+        .materials
+        .iter()
+        .any(|material| matches!(&material.image, Some(image) if image.palette.is_none()));
+    if palette.is_empty() && needs_palette {
+        return Err("No palette found, pass a palette.lmp".to_string());
+    }
+    Ok(palette)
+}
+
+/// Writes the map as an .obj, its .mtl and the textures as PNG next to it
+fn export_obj(filepath: &str, obj: &str, palette: Option<&str>) -> Result<(), String> {
+    let bsp = open(filepath)?;
+    let mesh = bsp.build_mesh(&bsp.read_header(), false);
+    let palette = read_palette(&mesh, palette)?;
+    let obj = Path::new(obj);
+    let directory = obj.parent().unwrap_or(Path::new(""));
+    let mtl = obj.with_extension("mtl");
+    let mtl_file = mtl.file_name().unwrap_or_default().to_string_lossy();
+
+    let create = |path: &Path| {
+        File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    mesh.write_obj(&mut create(obj)?, &mtl_file)
+        .map_err(|e| format!("{}: {}", obj.display(), e))?;
+    mesh.write_mtl(&mut create(&mtl)?)
+        .map_err(|e| format!("{}: {}", mtl.display(), e))?;
+
+    for material in &mesh.materials {
+        let Some(image) = &material.image else {
+            continue;
+        };
+        let target = directory.join(material.texture_file());
+        image
+            .save_png(&target.to_string_lossy(), &palette)
+            .map_err(|e| format!("{}: {}", target.display(), e))?;
+    }
+
+    println!("{}: {} vertices", obj.display(), mesh.positions.len());
+    Ok(())
+}
+
+/// Writes the map as a binary glTF with its textures embedded
+fn export_glb(
+    filepath: &str,
+    glb: &str,
+    palette: Option<&str>,
+    lightmaps: bool,
+) -> Result<(), String> {
+    let bsp = open(filepath)?;
+    let mesh = bsp.build_mesh(&bsp.read_header(), lightmaps);
+    let palette = read_palette(&mesh, palette)?;
+    let mut writer = File::create(glb)
+        .map(BufWriter::new)
+        .map_err(|e| format!("{}: {}", glb, e))?;
+    mesh.write_glb(&mut writer, &palette)
+        .map_err(|e| format!("{}: {}", glb, e))?;
+    println!("{}: {} vertices", glb, mesh.positions.len());
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::{fs, path::Path};
+
+use quake::image::IndexedImage;
+use quake::pak::{self, normalize_path, Pak, PakWriter};
+use quake::palette::convert_palette;
+use quake::wad::{Lump, Wad};
+
+const USAGE: &str = "Usage (<pak> can also be a .pk3):
+    qpak list <pak>
+    qpak extract <pak> <output dir> [pattern]
+    qpak create <pak> <input dir>
+    qpak verify <pak>
+    qpak png <wad or pak> <output dir> [palette.lmp]";
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["list", pak] => list(pak),
+        ["extract", pak, output] => extract(pak, output, "*"),
+        ["extract", pak, output, pattern] => extract(pak, output, pattern),
+        ["create", pak, input] => create(pak, input),
+        ["verify", pak] => verify(pak),
+        ["png", file, output] => export_png(file, output, None),
+        ["png", file, output, palette] => export_png(file, output, Some(palette)),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn open(filepath: &str) -> Result<Pak, String> {
+    Pak::open_mapped(filepath).map_err(|e| format!("{}: {}", filepath, e))
+}
+
+/// Prints name, offset and size of every entry
+fn list(filepath: &str) -> Result<(), String> {
+    let pak = open(filepath)?;
+    for file in &pak.files {
+        println!(
+            "{:<56} {:>10} {:>10}",
+            file.name, file.file_offset, file.file_size
+        );
+    }
+    println!("{} files", pak.files.len());
+    Ok(())
+}
+
+/// Writes every entry matching the pattern below the output directory
+fn extract(filepath: &str, output: &str, pattern: &str) -> Result<(), String> {
+    let pak = open(filepath)?;
+    let pattern = normalize_path(pattern);
+    let mut count = 0;
+
+    for (i, file) in pak.files.iter().enumerate() {
+        let name = normalize_path(&file.name);
+        if !glob_match(&pattern, &name) {
+            continue;
+        }
+        // Never write outside the output directory
+        if name.split('/').any(|part| part == "..") {
+            eprintln!("Skipping {}: path leaves the output directory", file.name);
+            continue;
+        }
+
+        let data = pak
+            .file_data(i)
+            .map_err(|e| format!("{}: {}", file.name, e))?;
+        let target = Path::new(output).join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        fs::write(&target, data).map_err(|e| format!("{}: {}", target.display(), e))?;
+        println!("{}", name);
+        count += 1;
+    }
+
+    println!("{} files extracted", count);
+    Ok(())
+}
+
+/// Packs every file below the input directory
+fn create(filepath: &str, input: &str) -> Result<(), String> {
+    let mut writer = PakWriter::new();
+    writer
+        .add_directory(input)
+        .map_err(|e| format!("{}: {}", input, e))?;
+    writer
+        .save(filepath)
+        .map_err(|e| format!("{}: {}", filepath, e))
+}
+
+/// Checks that every entry lies inside the file and names are null terminated
+fn verify(filepath: &str) -> Result<(), String> {
+    let data = fs::read(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+    let problems = pak::verify(&data).map_err(|e| format!("{}: {}", filepath, e))?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    if problems.is_empty() {
+        println!("{}: OK", filepath);
+        Ok(())
+    } else {
+        Err(format!("{}: {} problems found", filepath, problems.len()))
+    }
+}
+
+/// Writes every picture of a WAD2/WAD3, or every .lmp picture of a PAK, as PNG
+fn export_png(filepath: &str, output: &str, palette: Option<&str>) -> Result<(), String> {
+    let data = fs::read(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+    let palette = match palette {
+        Some(palette) => Some(convert_palette(
+            &fs::read(palette).map_err(|e| format!("{}: {}", palette, e))?,
+        )),
+        None => None,
+    };
+    fs::create_dir_all(output).map_err(|e| format!("{}: {}", output, e))?;
+
+    let mut images = Vec::new();
+    let palette = if data.starts_with(b"WAD2") || data.starts_with(b"WAD3") {
+        let wad = Wad::new(data);
+        let directory = wad
+            .read_directory()
+            .map_err(|e| format!("{}: {}", filepath, e))?;
+        let mut wad_palette = None;
+        for asset in directory {
+            match wad.decode(&asset) {
+                Ok(Lump::Picture(image)) => images.push((asset.name, image)),
+                Ok(Lump::MipTex(miptex)) => match miptex.mips.into_iter().next() {
+                    Some(image) => images.push((asset.name, image)),
+                    None => eprintln!("Skipping {}: no pixels", asset.name),
+                },
+                // The "palette" lump of gfx.wad, the first one if several
+                Ok(Lump::Palette(palette))
+                    if wad_palette.is_none() && asset.name.eq_ignore_ascii_case("palette") =>
+                {
+                    wad_palette = Some(palette)
+                }
+                Ok(Lump::Palette(_)) => {}
+                Err(e) => eprintln!("Skipping {}", e),
+            }
+        }
+        palette.or(wad_palette)
+    } else {
+        let pak = Pak::from_bytes(data).map_err(|e| format!("{}: {}", filepath, e))?;
+        for (i, file) in pak.files.iter().enumerate() {
+            let name = normalize_path(&file.name);
+            let Some(stem) = name.strip_suffix(".lmp") else {
+                continue;
+            };
+            let data = pak
+                .file_data(i)
+                .map_err(|e| format!("{}: {}", file.name, e))?;
+            // palette.lmp, colormap.lmp... are not pictures
+            match IndexedImage::from_qpic(data) {
+                Ok(image) if data.len() == 8 + image.pixels.len() => {
+                    images.push((stem.to_string(), image))
+                }
+                _ => eprintln!("Skipping {}: not a picture", file.name),
+            }
+        }
+        match palette {
+            Some(palette) => Some(palette),
+            None => pak
+                .find_file("gfx/palette.lmp")
+                .map_err(|e| format!("{}: {}", filepath, e))?
+                .map(convert_palette),
+        }
+    };
+    // Half-Life images bring their own
+    if palette.is_none() && images.iter().any(|(_, image)| image.palette.is_none()) {
+        return Err("No palette found, pass a palette.lmp".to_string());
+    }
+    let palette = palette.unwrap_or_default();
+
+    for (name, image) in &images {
+        // "*water" and friends, "*" is not allowed in file names everywhere
+        let target = Path::new(output).join(format!("{}.png", name.replace('*', "#")));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        image
+            .save_png(&target.to_string_lossy(), &palette)
+            .map_err(|e| format!("{}: {}", target.display(), e))?;
+        println!("{}", target.display());
+    }
+
+    println!("{} images exported", images.len());
+    Ok(())
+}
+
+/// Matches a path against a pattern where "*" and "?" are wildcards
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last "*" swallow one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
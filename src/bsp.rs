@@ -1,16 +1,42 @@
 use std::io::{self, Read};
 
+pub use self::clipnodes::{ClipNode, Hull, HullSize, Hulls, Trace};
 pub use self::edges::Edge;
+pub use self::entities::{parse_entities, write_entities, Entity};
+pub use self::error::BspError;
 pub use self::faces::Face;
+pub use self::lightmaps::{LightStyles, Lightmap, LIGHT_STYLES, MAX_LIGHTSTYLES};
+pub use self::mesh::{LightmapAtlas, MapMesh, MeshMaterial};
+pub use self::miptex::{texture_animations, TextureAnimation, TextureKind};
+pub use self::models::BspModel;
+pub use self::nodes::{
+    BspTree, Leaf, Node, NodeChild, CONTENTS_EMPTY, CONTENTS_LAVA, CONTENTS_SKY, CONTENTS_SLIME,
+    CONTENTS_SOLID, CONTENTS_WATER,
+};
 pub use self::planes::Plane;
+pub use self::texinfo::{SurfaceExtents, TexInfo, TEX_SPECIAL};
 pub use self::vertices::Vertex;
+pub use self::visibility::decompress_vis;
+pub use self::writer::BspWriter;
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use glam::Vec3;
+mod clipnodes;
 mod edges;
-//mod entities;
+mod entities;
+mod error;
 mod faces;
+mod lightmaps;
+mod mesh;
+mod miptex;
+mod models;
+mod nodes;
 mod planes;
+mod texinfo;
+mod validate;
 mod vertices;
+mod visibility;
+mod writer;
 
 pub struct Bsp {
     data: Vec<u8>,
@@ -18,7 +44,7 @@ pub struct Bsp {
 
 #[derive(Debug)]
 pub struct BspHeader {
-    pub version: u32, // Must be 29 (BSP29 for Quake)
+    pub version: u32, // 29 for Quake, 30 for Half-Life, or the "BSP2" / "2PSB" magic
     pub entities: BspEntry,
     pub planes: BspEntry,
 
@@ -44,6 +70,99 @@ pub struct BspHeader {
     pub models: BspEntry,
 }
 
+impl BspHeader {
+    /// Lump layout of the file, BSP29 for unknown versions (rejected by `Bsp::load`)
+    pub fn format(&self) -> BspFormat {
+        BspFormat::from_version(self.version).unwrap_or(BspFormat::Bsp29)
+    }
+
+    /// Returns every lump entry with its name, in file order
+    pub fn lumps(&self) -> [(&'static str, &BspEntry); 15] {
+        [
+            ("entities", &self.entities),
+            ("planes", &self.planes),
+            ("miptex", &self.miptex),
+            ("vertices", &self.vertices),
+            ("visilist", &self.visilist),
+            ("nodes", &self.nodes),
+            ("texinfo", &self.texinfo),
+            ("faces", &self.faces),
+            ("lightmaps", &self.lightmaps),
+            ("clipnodes", &self.clipnodes),
+            ("leaves", &self.leaves),
+            ("lfaces", &self.lfaces),
+            ("edges", &self.edges),
+            ("ledges", &self.ledges),
+            ("models", &self.models),
+        ]
+    }
+}
+
+/// Layout of the lumps, told by the header version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BspFormat {
+    Bsp29,   // Quake
+    Bsp2Rmq, // "2PSB": 32-bit indices, 16-bit bounding boxes
+    Bsp2,    // "BSP2": 32-bit indices, float bounding boxes
+    Bsp30,   // Half-Life: BSP29 records, RGB lightmaps, textures with their own palette
+}
+
+pub const BSP29_VERSION: u32 = 29;
+pub const BSP30_VERSION: u32 = 30;
+pub const BSP2RMQ_VERSION: u32 = u32::from_le_bytes(*b"2PSB");
+pub const BSP2_VERSION: u32 = u32::from_le_bytes(*b"BSP2");
+
+impl BspFormat {
+    pub fn from_version(version: u32) -> Option<Self> {
+        match version {
+            BSP29_VERSION => Some(BspFormat::Bsp29),
+            BSP30_VERSION => Some(BspFormat::Bsp30),
+            BSP2RMQ_VERSION => Some(BspFormat::Bsp2Rmq),
+            BSP2_VERSION => Some(BspFormat::Bsp2),
+            _ => None,
+        }
+    }
+
+    /// Whether indices are 32-bit (BSP2 and 2PSB)
+    pub fn is_bsp2(self) -> bool {
+        matches!(self, BspFormat::Bsp2Rmq | BspFormat::Bsp2)
+    }
+
+    /// Bytes per luxel: RGB for Half-Life, a single brightness otherwise
+    pub fn lightmap_channels(self) -> u32 {
+        match self {
+            BspFormat::Bsp30 => 3,
+            _ => 1,
+        }
+    }
+
+    /// Size of one record of a lump, 1 for the ones without fixed records
+    pub fn record_size(self, lump: &str) -> usize {
+        match (lump, self) {
+            ("planes", _) => 20,
+            ("vertices", _) => 12,
+            ("texinfo", _) => 40,
+            ("ledges", _) => 4,
+            ("models", _) => 64,
+            ("nodes", BspFormat::Bsp29 | BspFormat::Bsp30) => 24,
+            ("nodes", BspFormat::Bsp2Rmq) => 32,
+            ("nodes", BspFormat::Bsp2) => 44,
+            ("faces", BspFormat::Bsp29 | BspFormat::Bsp30) => 20,
+            ("faces", _) => 28,
+            ("clipnodes", BspFormat::Bsp29 | BspFormat::Bsp30) => 8,
+            ("clipnodes", _) => 12,
+            ("leaves", BspFormat::Bsp29 | BspFormat::Bsp30) => 28,
+            ("leaves", BspFormat::Bsp2Rmq) => 32,
+            ("leaves", BspFormat::Bsp2) => 44,
+            ("lfaces", BspFormat::Bsp29 | BspFormat::Bsp30) => 2,
+            ("lfaces", _) => 4,
+            ("edges", BspFormat::Bsp29 | BspFormat::Bsp30) => 4,
+            ("edges", _) => 8,
+            _ => 1, // entities, miptex, visilist, lightmaps
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BspEntry {
     pub offset: u32,
@@ -51,8 +170,23 @@ pub struct BspEntry {
 }
 
 impl Bsp {
-    pub fn new(data: Vec<u8>) -> Self {
-        Bsp { data }
+    /// Checks the version, the lump bounds and sizes and the indices between lumps, so the
+    /// readers cannot fail on the returned map
+    pub fn load(data: Vec<u8>) -> Result<Self, BspError> {
+        if data.len() < validate::HEADER_SIZE {
+            return Err(BspError::TruncatedHeader { size: data.len() });
+        }
+        let bsp = Bsp { data };
+        let header = bsp.read_header();
+        if BspFormat::from_version(header.version).is_none() {
+            return Err(BspError::UnsupportedVersion(header.version));
+        }
+        bsp.validate(&header)?;
+        Ok(bsp)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.read_header().version
     }
 
     pub fn read_header(&self) -> BspHeader {
@@ -74,7 +208,7 @@ impl Bsp {
             BspEntry { offset, size }
         };
 
-        BspHeader {
+        let mut header = BspHeader {
             version,
             entities: read_entry(&mut cursor),
             planes: read_entry(&mut cursor),
@@ -91,7 +225,18 @@ impl Bsp {
             edges: read_entry(&mut cursor),
             ledges: read_entry(&mut cursor),
             models: read_entry(&mut cursor),
+        };
+
+        // Half-Life maps keep the Quake lump order, but Blue Shift ones swap the entities
+        // and the planes: tell them apart by which one holds whole 20 byte planes
+        if version == BSP30_VERSION
+            && !header.planes.size.is_multiple_of(20)
+            && header.entities.size.is_multiple_of(20)
+        {
+            std::mem::swap(&mut header.entities, &mut header.planes);
         }
+
+        header
     }
 
     pub fn read_ledges(&self, header: &BspHeader) -> Vec<i32> {
@@ -111,3 +256,39 @@ impl Bsp {
         ledges
     }
 }
+
+fn read_vec3(cursor: &mut io::Cursor<&[u8]>) -> Vec3 {
+    let x = cursor.read_f32::<LittleEndian>().unwrap();
+    let y = cursor.read_f32::<LittleEndian>().unwrap();
+    let z = cursor.read_f32::<LittleEndian>().unwrap();
+    Vec3::new(x, y, z)
+}
+
+/// Reads an index that is u16 in BSP29 files and u32 in BSP2 ones
+fn read_index(cursor: &mut io::Cursor<&[u8]>, format: BspFormat) -> u32 {
+    if format.is_bsp2() {
+        cursor.read_u32::<LittleEndian>().unwrap()
+    } else {
+        cursor.read_u16::<LittleEndian>().unwrap() as u32
+    }
+}
+
+/// Reads a node or clipnode child, i16 in BSP29 files and i32 in BSP2 ones
+fn read_child(cursor: &mut io::Cursor<&[u8]>, format: BspFormat) -> i32 {
+    if format.is_bsp2() {
+        cursor.read_i32::<LittleEndian>().unwrap()
+    } else {
+        cursor.read_i16::<LittleEndian>().unwrap() as i32
+    }
+}
+
+/// Reads a bounding box corner, three i16 except in BSP2 files where they are floats
+fn read_bounds(cursor: &mut io::Cursor<&[u8]>, format: BspFormat) -> Vec3 {
+    if format == BspFormat::Bsp2 {
+        return read_vec3(cursor);
+    }
+    let x = cursor.read_i16::<LittleEndian>().unwrap() as f32;
+    let y = cursor.read_i16::<LittleEndian>().unwrap() as f32;
+    let z = cursor.read_i16::<LittleEndian>().unwrap() as f32;
+    Vec3::new(x, y, z)
+}
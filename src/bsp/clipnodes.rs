@@ -0,0 +1,366 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use glam::Vec3;
+
+use super::{
+    read_child, Bsp, BspFormat, BspHeader, NodeChild, Plane, CONTENTS_EMPTY, CONTENTS_SOLID,
+};
+
+/// Which of the three hulls to collide with, by the size of the moving box
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HullSize {
+    Point,  // hull 0: bullets, missiles
+    Player, // hull 1, 32x32x56: player, most monsters
+    Large,  // hull 2, 64x64x88: shambler, fiend
+}
+
+/// Moves end points away from a hit plane, so the next move does not start inside it
+const DIST_EPSILON: f32 = 0.03125;
+
+#[derive(Debug, Clone)]
+pub struct ClipNode {
+    pub plane_id: u32,
+    pub children: [i32; 2], // front and back, negative for contents (CONTENTS_*)
+}
+
+/// Collision tree for one box size: the map brushes expanded by that box
+#[derive(Debug, Clone)]
+pub struct Hull {
+    pub clipnodes: Vec<ClipNode>,
+    pub headnode: i32, // root of the world model, negative contents for an empty hull
+    pub clip_mins: Vec3,
+    pub clip_maxs: Vec3,
+}
+
+/// The three collision hulls of the map and the planes they split on
+pub struct Hulls {
+    pub planes: Vec<Plane>,
+    pub hulls: [Hull; 3],
+}
+
+/// Result of a trace, like trace_t
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub all_solid: bool,   // the whole move is inside solid
+    pub start_solid: bool, // the move started inside solid
+    pub in_open: bool,
+    pub in_water: bool,
+    pub fraction: f32,        // how far the move went, 1.0 if nothing was hit
+    pub end_pos: Vec3,        // where the move stopped
+    pub plane: Option<Plane>, // surface hit, facing the start point
+}
+
+impl Bsp {
+    pub fn read_clipnodes(&self, header: &BspHeader) -> Vec<ClipNode> {
+        let start = header.clipnodes.offset as usize;
+        let end = start + header.clipnodes.size as usize;
+
+        let format = header.format();
+        let record_size = format.record_size("clipnodes");
+
+        let mut clipnodes = Vec::new();
+        let mut cursor = Cursor::new(&self.data[start..end]);
+
+        while (cursor.position() as usize) + record_size <= header.clipnodes.size as usize {
+            let plane_id = cursor.read_u32::<LittleEndian>().unwrap();
+            let front = read_child(&mut cursor, format);
+            let back = read_child(&mut cursor, format);
+
+            clipnodes.push(ClipNode {
+                plane_id,
+                children: [front, back],
+            });
+        }
+
+        clipnodes
+    }
+
+    /// Builds the point hull from the nodes, and the two box hulls from the clipnodes
+    pub fn read_hulls(&self, header: &BspHeader) -> Hulls {
+        let leaves = self.read_leaves(header);
+        let headnodes = self
+            .read_models(header)
+            .first()
+            .map_or([0; 4], |world| world.headnodes);
+
+        // Hull 0 is the rendering tree with the leaves replaced by their contents
+        let point_clipnodes = self
+            .read_nodes(header)
+            .iter()
+            .map(|node| {
+                let mut children = [0; 2];
+                for (side, child) in children.iter_mut().enumerate() {
+                    *child = match node.child(side) {
+                        NodeChild::Node(index) => index as i32,
+                        NodeChild::Leaf(index) => leaves
+                            .get(index)
+                            .map_or(CONTENTS_SOLID, |leaf| leaf.contents),
+                    };
+                }
+                ClipNode {
+                    plane_id: node.plane_id,
+                    children,
+                }
+            })
+            .collect();
+        let clipnodes = self.read_clipnodes(header);
+
+        // Half-Life boxes are centred on the origin (its crouching hull 3 is not kept)
+        let (player, large) = if header.format() == BspFormat::Bsp30 {
+            (
+                (Vec3::new(-16.0, -16.0, -36.0), Vec3::new(16.0, 16.0, 36.0)),
+                (Vec3::splat(-32.0), Vec3::splat(32.0)),
+            )
+        } else {
+            (
+                (Vec3::new(-16.0, -16.0, -24.0), Vec3::new(16.0, 16.0, 32.0)),
+                (Vec3::new(-32.0, -32.0, -24.0), Vec3::new(32.0, 32.0, 64.0)),
+            )
+        };
+
+        Hulls {
+            planes: self.read_planes(header),
+            hulls: [
+                Hull {
+                    clipnodes: point_clipnodes,
+                    headnode: headnodes[0],
+                    clip_mins: Vec3::ZERO,
+                    clip_maxs: Vec3::ZERO,
+                },
+                Hull {
+                    clipnodes: clipnodes.clone(),
+                    headnode: headnodes[1],
+                    clip_mins: player.0,
+                    clip_maxs: player.1,
+                },
+                Hull {
+                    clipnodes,
+                    headnode: headnodes[2],
+                    clip_mins: large.0,
+                    clip_maxs: large.1,
+                },
+            ],
+        }
+    }
+}
+
+impl Hulls {
+    /// Returns the contents (CONTENTS_*) at a point, like SV_HullPointContents.
+    ///
+    /// For the box hulls the point is the centre of the box origin, so CONTENTS_SOLID
+    /// means the box would overlap a wall.
+    pub fn point_contents(&self, point: Vec3, hull: HullSize) -> i32 {
+        let hull = self.hull(hull);
+        self.hull_point_contents(hull, hull.headnode, point)
+    }
+
+    /// Moves a point (or a box centred on it for hulls 1 and 2) from start to end, stopping
+    /// at the first solid surface, like SV_RecursiveHullCheck
+    pub fn trace(&self, start: Vec3, end: Vec3, hull: HullSize) -> Trace {
+        let hull = self.hull(hull);
+        let mut trace = Trace {
+            all_solid: true,
+            start_solid: false,
+            in_open: false,
+            in_water: false,
+            fraction: 1.0,
+            end_pos: end,
+            plane: None,
+        };
+        self.recursive_hull_check(hull, hull.headnode, 0.0, 1.0, start, end, &mut trace);
+        trace
+    }
+
+    /// Returns the hull a box of that size collides with
+    pub fn hull(&self, size: HullSize) -> &Hull {
+        &self.hulls[size as usize]
+    }
+
+    fn hull_point_contents(&self, hull: &Hull, mut num: i32, point: Vec3) -> i32 {
+        while num >= 0 {
+            let node = &hull.clipnodes[num as usize];
+            let d = self.planes[node.plane_id as usize].distance(point);
+            num = if d < 0.0 {
+                node.children[1]
+            } else {
+                node.children[0]
+            };
+        }
+        num
+    }
+
+    /// Returns false once the trace hit something, true while it goes through
+    #[allow(clippy::too_many_arguments)]
+    fn recursive_hull_check(
+        &self,
+        hull: &Hull,
+        num: i32,
+        p1f: f32,
+        p2f: f32,
+        p1: Vec3,
+        p2: Vec3,
+        trace: &mut Trace,
+    ) -> bool {
+        // Reached a leaf
+        if num < 0 {
+            if num != CONTENTS_SOLID {
+                trace.all_solid = false;
+                if num == CONTENTS_EMPTY {
+                    trace.in_open = true;
+                } else {
+                    trace.in_water = true;
+                }
+            } else {
+                trace.start_solid = true;
+            }
+            return true; // empty
+        }
+
+        let node = &hull.clipnodes[num as usize];
+        let plane = &self.planes[node.plane_id as usize];
+        let t1 = plane.distance(p1);
+        let t2 = plane.distance(p2);
+
+        // Both ends on the same side
+        if t1 >= 0.0 && t2 >= 0.0 {
+            return self.recursive_hull_check(hull, node.children[0], p1f, p2f, p1, p2, trace);
+        }
+        if t1 < 0.0 && t2 < 0.0 {
+            return self.recursive_hull_check(hull, node.children[1], p1f, p2f, p1, p2, trace);
+        }
+
+        // Split the move at the plane, nudged back toward the start
+        let mut frac = if t1 < 0.0 {
+            (t1 + DIST_EPSILON) / (t1 - t2)
+        } else {
+            (t1 - DIST_EPSILON) / (t1 - t2)
+        }
+        .clamp(0.0, 1.0);
+        let mut midf = p1f + (p2f - p1f) * frac;
+        let mut mid = p1 + frac * (p2 - p1);
+        let side = if t1 < 0.0 { 1 } else { 0 };
+
+        // Move up to the plane
+        if !self.recursive_hull_check(hull, node.children[side], p1f, midf, p1, mid, trace) {
+            return false;
+        }
+
+        // Go past it if the other side is not solid
+        if self.hull_point_contents(hull, node.children[side ^ 1], mid) != CONTENTS_SOLID {
+            return self.recursive_hull_check(
+                hull,
+                node.children[side ^ 1],
+                midf,
+                p2f,
+                mid,
+                p2,
+                trace,
+            );
+        }
+
+        if trace.all_solid {
+            return false; // never got out of the solid area
+        }
+
+        // The other side is solid: this is the impact point
+        trace.plane = Some(if side == 0 {
+            plane.clone()
+        } else {
+            Plane {
+                normal: -plane.normal,
+                dist: -plane.dist,
+                // Axial types 0 to 2 become their non axial 3 to 5
+                plane_type: if plane.plane_type < 3 {
+                    plane.plane_type + 3
+                } else {
+                    plane.plane_type
+                },
+            }
+        });
+
+        // Back up until the point is out of the solid, the epsilon may not be enough
+        while self.hull_point_contents(hull, hull.headnode, mid) == CONTENTS_SOLID {
+            frac -= 0.1;
+            if frac < 0.0 {
+                trace.fraction = midf;
+                trace.end_pos = mid;
+                return false;
+            }
+            midf = p1f + (p2f - p1f) * frac;
+            mid = p1 + frac * (p2 - p1);
+        }
+
+        trace.fraction = midf;
+        trace.end_pos = mid;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty in front of the plane x = 0, solid behind, the same for every hull
+    fn half_space() -> Hulls {
+        let hull = Hull {
+            clipnodes: vec![ClipNode {
+                plane_id: 0,
+                children: [CONTENTS_EMPTY, CONTENTS_SOLID],
+            }],
+            headnode: 0,
+            clip_mins: Vec3::ZERO,
+            clip_maxs: Vec3::ZERO,
+        };
+        Hulls {
+            planes: vec![Plane {
+                normal: Vec3::X,
+                dist: 0.0,
+                plane_type: 0,
+            }],
+            hulls: [hull.clone(), hull.clone(), hull],
+        }
+    }
+
+    #[test]
+    fn point_contents() {
+        let hulls = half_space();
+        assert_eq!(
+            hulls.point_contents(Vec3::new(5.0, 0.0, 0.0), HullSize::Point),
+            CONTENTS_EMPTY
+        );
+        assert_eq!(
+            hulls.point_contents(Vec3::new(-5.0, 0.0, 0.0), HullSize::Player),
+            CONTENTS_SOLID
+        );
+    }
+
+    #[test]
+    fn trace_stops_before_the_plane() {
+        let hulls = half_space();
+        let trace = hulls.trace(
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(-10.0, 0.0, 0.0),
+            HullSize::Point,
+        );
+        assert!(!trace.all_solid && !trace.start_solid && trace.in_open);
+        assert!((trace.fraction - (10.0 - DIST_EPSILON) / 20.0).abs() < 1e-6);
+        assert!((trace.end_pos.x - DIST_EPSILON).abs() < 1e-6);
+        assert_eq!(trace.plane.unwrap().normal, Vec3::X);
+
+        let trace = hulls.trace(
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            HullSize::Point,
+        );
+        assert_eq!(trace.fraction, 1.0);
+        assert!(trace.plane.is_none());
+
+        let trace = hulls.trace(
+            Vec3::new(-5.0, 0.0, 0.0),
+            Vec3::new(-10.0, 0.0, 0.0),
+            HullSize::Large,
+        );
+        assert!(trace.all_solid && trace.start_solid);
+    }
+}
@@ -1,29 +1,26 @@
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
-use super::{Bsp, BspHeader};
+use super::{read_index, Bsp, BspHeader};
 
 #[derive(Debug)]
 pub struct Edge {
-    pub start_vertex: u16,
-    pub end_vertex: u16,
+    pub start_vertex: u32, // u16 in BSP29 files
+    pub end_vertex: u32,
 }
 
 impl Bsp {
     pub fn read_edges(&self, header: &BspHeader) -> Vec<Edge> {
         let start = header.edges.offset as usize;
         let end = start + header.edges.size as usize;
+        let format = header.format();
+        let record_size = format.record_size("edges");
 
         let mut edges = Vec::new();
         let mut cursor = Cursor::new(&self.data[start..end]);
 
-        while (cursor.position() as usize) < header.edges.size as usize {
-            let mut buffer = [0u8; 4]; // 2 u16 = 4 bytes
-            if cursor.read_exact(&mut buffer).is_err() {
-                break;
-            }
-
-            let start_vertex = u16::from_le_bytes(buffer[0..2].try_into().unwrap());
-            let end_vertex = u16::from_le_bytes(buffer[2..4].try_into().unwrap());
+        while (cursor.position() as usize) + record_size <= header.edges.size as usize {
+            let start_vertex = read_index(&mut cursor, format);
+            let end_vertex = read_index(&mut cursor, format);
 
             edges.push(Edge {
                 start_vertex,
@@ -1,53 +1,358 @@
+use std::io;
+
 use glam::Vec3;
 
 use super::{Bsp, BspHeader};
 
-#[derive(Debug)]
+/// One `{ "key" "value" ... }` block of the entity lump
+#[derive(Debug, Clone, Default)]
 pub struct Entity {
-    pub classname: String,
-    pub origin: Vec3,
-    pub angle: u32,
-    pub light: u32,
-    pub target: String,
-    pub killtarget: String,
-    pub spawnflags: u32,
-    pub style: u32,
-    pub message: String,
-    pub mangle: Vec3,
-    pub speed: u32,
-    pub wait: u32,
-    pub lip: u32,
-    pub damage: u32,
-    pub health: u32,
-    pub delay: u32,
-    pub sounds: u32,
-    pub wad: String,
-    pub height: u32,
+    /// Every key/value pair, in file order
+    pub fields: Vec<(String, String)>,
 }
 
 impl Bsp {
-    pub fn read_entities(&self, header: &BspHeader) -> Vec<Entity> {
-        // Assuming the BSP has an `entities` field that is a string containing all entities
-        let 
-        let mut entities = Vec::new();
+    pub fn read_entities(&self, header: &BspHeader) -> io::Result<Vec<Entity>> {
+        parse_entities(&self.read_entities_text(header)?)
+    }
+
+    /// Returns the entity lump as is, the content of a .ent file
+    pub fn read_entities_text(&self, header: &BspHeader) -> io::Result<String> {
+        let start = header.entities.offset as usize;
+        let end = start + header.entities.size as usize;
+        let data = self.data.get(start..end).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Entity lump lies outside the file",
+            )
+        })?;
+
+        // The lump is a null terminated string
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        Ok(String::from_utf8_lossy(&data[..len]).to_string())
+    }
+}
+
+/// Parses entity text, as found in the entity lump or a .ent file
+pub fn parse_entities(text: &str) -> io::Result<Vec<Entity>> {
+    let mut tokens = Tokenizer { text };
+    let mut entities = Vec::new();
+
+    while let Some(token) = tokens.next_token() {
+        if token != "{" {
+            return Err(parse_error(format!("expected {{, found {:?}", token)));
+        }
+
+        let mut entity = Entity::default();
+        loop {
+            let key = match tokens.next_token() {
+                Some("}") => break,
+                Some(key) => key.to_string(),
+                None => return Err(parse_error("EOF without closing brace".to_string())),
+            };
+            let value = match tokens.next_token() {
+                Some("}") | None => {
+                    return Err(parse_error(format!("key {:?} without a value", key)))
+                }
+                Some(value) => value.to_string(),
+            };
+            entity.fields.push((key, value));
+        }
+        entities.push(entity);
+    }
+
+    Ok(entities)
+}
+
+/// Writes entities back as text, the way qbsp lays out the entity lump
+pub fn write_entities(entities: &[Entity]) -> String {
+    let mut text = String::new();
+    for entity in entities {
+        text.push_str("{\n");
+        for (key, value) in &entity.fields {
+            text.push_str(&format!("\"{}\" \"{}\"\n", key, value));
+        }
+        text.push_str("}\n");
+    }
+    text
+}
+
+impl Entity {
+    /// Returns the value of a key, the last one wins when a key is repeated like in Quake
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets a key, in place of its first value, and drops the repeated ones
+    pub fn set(&mut self, key: &str, value: &str) {
+        let Some(first) = self.fields.iter().position(|(k, _)| k == key) else {
+            self.fields.push((key.to_string(), value.to_string()));
+            return;
+        };
+        self.fields[first].1 = value.to_string();
+        let mut i = 0;
+        self.fields.retain(|(k, _)| {
+            i += 1;
+            k != key || i - 1 == first
+        });
+    }
+
+    /// Removes every value of a key, returns the one `get` gave
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.get(key).map(str::to_string);
+        self.fields.retain(|(k, _)| k != key);
+        value
+    }
+
+    pub fn classname(&self) -> &str {
+        self.get("classname").unwrap_or("")
+    }
+
+    pub fn origin(&self) -> Option<Vec3> {
+        self.get_vec3("origin")
+    }
+
+    /// Yaw in degrees, -1 is up and -2 is down for movers
+    pub fn angle(&self) -> Option<f32> {
+        self.get_f32("angle")
+    }
+
+    /// Pitch, yaw and roll in degrees (info_intermission cameras)
+    pub fn mangle(&self) -> Option<Vec3> {
+        self.get_vec3("mangle")
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.get("target")
+    }
+
+    pub fn targetname(&self) -> Option<&str> {
+        self.get("targetname")
+    }
+
+    pub fn killtarget(&self) -> Option<&str> {
+        self.get("killtarget")
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.get("message")
+    }
+
+    /// Brush model "*1", "*2"... or model path for point entities
+    pub fn model(&self) -> Option<&str> {
+        self.get("model")
+    }
+
+    /// Index of the inline brush model ("*3" is model 3)
+    pub fn brush_model(&self) -> Option<usize> {
+        self.model()?.strip_prefix('*')?.parse().ok()
+    }
+
+    /// Texture wads used to compile the map (worldspawn)
+    pub fn wad(&self) -> Option<&str> {
+        self.get("wad")
+    }
+
+    pub fn spawnflags(&self) -> u32 {
+        self.get_f32("spawnflags").unwrap_or(0.0) as u32
+    }
+
+    pub fn style(&self) -> u32 {
+        self.get_f32("style").unwrap_or(0.0) as u32
+    }
+
+    pub fn sounds(&self) -> u32 {
+        self.get_f32("sounds").unwrap_or(0.0) as u32
+    }
+
+    pub fn light(&self) -> Option<f32> {
+        self.get_f32("light")
+    }
+
+    pub fn speed(&self) -> Option<f32> {
+        self.get_f32("speed")
+    }
+
+    pub fn wait(&self) -> Option<f32> {
+        self.get_f32("wait")
+    }
+
+    pub fn lip(&self) -> Option<f32> {
+        self.get_f32("lip")
+    }
+
+    pub fn damage(&self) -> Option<f32> {
+        self.get_f32("dmg")
+    }
+
+    pub fn health(&self) -> Option<f32> {
+        self.get_f32("health")
+    }
+
+    pub fn delay(&self) -> Option<f32> {
+        self.get_f32("delay")
+    }
 
-        // Extract the entity data from the BSP file using the header
-       
+    pub fn height(&self) -> Option<f32> {
+        self.get_f32("height")
+    }
 
+    /// Parses a number the way atof does, ignoring anything after it
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        atof(self.get(key)?)
+    }
 
+    /// Parses "x y z", each number like `get_f32`
+    pub fn get_vec3(&self, key: &str) -> Option<Vec3> {
+        let mut parts = self.get(key)?.split_whitespace().map(atof);
+        let x = parts.next()??;
+        let y = parts.next()??;
+        let z = parts.next()??;
+        Some(Vec3::new(x, y, z))
+    }
+}
 
-            entities.push(entity);
+/// Splits entity text into braces and quoted or bare words, skipping // comments
+struct Tokenizer<'a> {
+    text: &'a str,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn next_token(&mut self) -> Option<&'a str> {
+        loop {
+            self.text = self.text.trim_start();
+            if let Some(rest) = self.text.strip_prefix("//") {
+                self.text = rest.find('\n').map_or("", |i| &rest[i..]);
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = self.text.char_indices();
+        let (_, first) = chars.next()?;
+        if first == '{' || first == '}' {
+            let token = &self.text[..1];
+            self.text = &self.text[1..];
+            return Some(token);
         }
 
-        entities
+        if first == '"' {
+            let rest = &self.text[1..];
+            let end = rest.find('"').unwrap_or(rest.len());
+            let token = &rest[..end];
+            self.text = rest.get(end + 1..).unwrap_or("");
+            return Some(token);
+        }
+
+        let end = self
+            .text
+            .find(|c: char| c.is_whitespace() || c == '{' || c == '}' || c == '"')
+            .unwrap_or(self.text.len());
+        let token = &self.text[..end];
+        self.text = &self.text[end..];
+        Some(token)
     }
 }
 
-fn parse_key_value(line: &str) -> Option<(String, String)> {
-    let mut parts = line.splitn(2, '\"');
-    parts.next()?; // Skip the initial opening quote
-    let key = parts.next()?.trim().to_string();
-    parts.next()?; // Skip the quote between key and value
-    let value = parts.next()?.trim().to_string();
-    Some((key, value))
+fn parse_error(message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Entity parse error: {}", message),
+    )
+}
+
+/// Reads the number at the start of a value: sign, digits, fraction and exponent, like atof.
+/// Unlike atof, a value without any digit is None rather than 0.
+fn atof(value: &str) -> Option<f32> {
+    let value = value.trim_start();
+    let bytes = value.as_bytes();
+    let digits = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        i
+    };
+
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let integer_end = digits(end);
+    let mut has_digits = integer_end > end;
+    end = integer_end;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction_end = digits(end + 1);
+        has_digits |= fraction_end > end + 1;
+        end = fraction_end;
+    }
+    if !has_digits {
+        return None;
+    }
+    // The exponent only counts if it has digits, "1e" is 1
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent_end = digits(end + 1 + sign);
+        if exponent_end > end + 1 + sign {
+            end = exponent_end;
+        }
+    }
+    value[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_parse_like_atof() {
+        let mut entity = Entity::default();
+        for (key, value) in [
+            ("speed", "+100"),
+            ("wait", "1.5e2 seconds"),
+            ("lip", "-.5"),
+            ("dmg", "abc"),
+            ("delay", "2e"),
+            ("origin", "+16 -1e1 8.5x"),
+        ] {
+            entity.fields.push((key.to_string(), value.to_string()));
+        }
+
+        assert_eq!(entity.speed(), Some(100.0));
+        assert_eq!(entity.wait(), Some(150.0));
+        assert_eq!(entity.lip(), Some(-0.5));
+        assert_eq!(entity.damage(), None);
+        assert_eq!(entity.delay(), Some(2.0));
+        assert_eq!(entity.origin(), Some(Vec3::new(16.0, -10.0, 8.5)));
+    }
+
+    #[test]
+    fn set_and_remove_keys() {
+        let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let mut entity = Entity {
+            fields: vec![
+                field("classname", "light"),
+                field("light", "200"),
+                field("style", "1"),
+                field("light", "300"),
+            ],
+        };
+
+        entity.set("light", "150");
+        entity.set("targetname", "lamp");
+        assert_eq!(
+            entity.fields,
+            [
+                field("classname", "light"),
+                field("light", "150"),
+                field("style", "1"),
+                field("targetname", "lamp"),
+            ]
+        );
+
+        assert_eq!(entity.remove("style").as_deref(), Some("1"));
+        assert_eq!(entity.remove("style"), None);
+        assert_eq!(entity.get("light"), Some("150"));
+        assert_eq!(entity.fields.len(), 3);
+    }
 }
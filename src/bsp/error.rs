@@ -0,0 +1,85 @@
+use std::{error::Error, fmt, io};
+
+/// Why a .bsp file was rejected by `Bsp::load`
+#[derive(Debug)]
+pub enum BspError {
+    /// The file is smaller than its header
+    TruncatedHeader { size: usize },
+    /// Not a version this engine reads (29, 30, BSP2 or 2PSB)
+    UnsupportedVersion(u32),
+    /// A lump lies past the end of the file
+    LumpOutOfBounds {
+        lump: &'static str,
+        offset: u32,
+        size: u32,
+        file_size: usize,
+    },
+    /// A lump size is not a whole number of records
+    BadLumpSize {
+        lump: &'static str,
+        size: u32,
+        record_size: usize,
+    },
+    /// A record refers to something that does not exist
+    BadIndex {
+        lump: &'static str,
+        record: usize,
+        field: &'static str,
+        index: i64,
+        count: usize,
+    },
+    /// A lump whose content could not be parsed (entities, textures)
+    InvalidLump { lump: &'static str, message: String },
+}
+
+impl fmt::Display for BspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BspError::TruncatedHeader { size } => {
+                write!(f, "BSP file of {} bytes is smaller than its header", size)
+            }
+            BspError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported BSP version {}", version)
+            }
+            BspError::LumpOutOfBounds {
+                lump,
+                offset,
+                size,
+                file_size,
+            } => write!(
+                f,
+                "{} lump ({} bytes at {}) lies outside the {} byte file",
+                lump, size, offset, file_size
+            ),
+            BspError::BadLumpSize {
+                lump,
+                size,
+                record_size,
+            } => write!(
+                f,
+                "{} lump size {} is not a multiple of {}",
+                lump, size, record_size
+            ),
+            BspError::BadIndex {
+                lump,
+                record,
+                field,
+                index,
+                count,
+            } => write!(
+                f,
+                "{} {}: {} {} out of range (0..{})",
+                lump, record, field, index, count
+            ),
+            BspError::InvalidLump { lump, message } => write!(f, "{} lump: {}", lump, message),
+        }
+    }
+}
+
+impl Error for BspError {}
+
+impl From<BspError> for io::Error {
+    fn from(error: BspError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
@@ -1,16 +1,17 @@
 use std::io::Cursor;
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use glam::{Vec2, Vec3};
 
-use super::{Bsp, BspHeader};
+use super::{read_index, Bsp, BspHeader, Edge, SurfaceExtents, TexInfo, Vertex};
 
 #[derive(Debug)]
 pub struct Face {
-    pub plane_id: u16,
-    pub side: u16,
+    pub plane_id: u32, // u16 in BSP29 files, like side, ledge_num and texinfo_id
+    pub side: u32,
     pub ledge_id: u32,
-    pub ledge_num: u16,
-    pub texinfo_id: u16,
+    pub ledge_num: u32,
+    pub texinfo_id: u32,
     pub typelight: u8,
     pub baselight: u8,
     pub light: [u8; 2],
@@ -20,23 +21,25 @@ pub struct Face {
 impl Bsp {
     pub fn read_faces(&self, header: &BspHeader) -> Vec<Face> {
         let start = header.faces.offset as usize;
-        let end = (header.faces.offset + header.faces.size) as usize;
+        let end = start + header.faces.size as usize;
+        let format = header.format();
+        let record_size = format.record_size("faces");
 
         let mut faces = Vec::new();
         let mut cursor = Cursor::new(&self.data[start..end]);
 
-        while (cursor.position() as usize) < (end - start) {
-            let plane_id = cursor.read_u16::<LittleEndian>().unwrap();
-            let side = cursor.read_u16::<LittleEndian>().unwrap();
+        while (cursor.position() as usize) + record_size <= header.faces.size as usize {
+            let plane_id = read_index(&mut cursor, format);
+            let side = read_index(&mut cursor, format);
             let ledge_id = cursor.read_u32::<LittleEndian>().unwrap();
-            let ledge_num = cursor.read_u16::<LittleEndian>().unwrap();
-            let texinfo_id = cursor.read_u16::<LittleEndian>().unwrap();
+            let ledge_num = read_index(&mut cursor, format);
+            let texinfo_id = read_index(&mut cursor, format);
             let typelight = cursor.read_u8().unwrap();
             let baselight = cursor.read_u8().unwrap();
 
             let mut light = [0; 2];
-            for i in 0..2 {
-                light[i] = cursor.read_u8().unwrap();
+            for level in light.iter_mut() {
+                *level = cursor.read_u8().unwrap();
             }
 
             let lightmap = cursor.read_u32::<LittleEndian>().unwrap();
@@ -57,3 +60,41 @@ impl Bsp {
         faces
     }
 }
+
+impl Face {
+    /// Returns the four light styles of the face, 255 marks the unused ones
+    pub fn styles(&self) -> [u8; 4] {
+        [self.typelight, self.baselight, self.light[0], self.light[1]]
+    }
+
+    /// Returns the polygon of the face, following its edges through the ledges list
+    pub fn vertices(&self, ledges: &[i32], edges: &[Edge], vertices: &[Vertex]) -> Vec<Vec3> {
+        let first = self.ledge_id as usize;
+        ledges[first..first + self.ledge_num as usize]
+            .iter()
+            .map(|&ledge| {
+                // Negative means the edge is walked backward
+                let vertex = if ledge >= 0 {
+                    edges[ledge as usize].start_vertex
+                } else {
+                    edges[ledge.unsigned_abs() as usize].end_vertex
+                };
+                vertices[vertex as usize].coordinates
+            })
+            .collect()
+    }
+
+    /// Returns the texture space extents of the face and the s/t coordinates of its vertices
+    pub fn texture_mapping(
+        &self,
+        texinfo: &[TexInfo],
+        face_vertices: &[Vec3],
+    ) -> (SurfaceExtents, Vec<Vec2>) {
+        let texinfo = &texinfo[self.texinfo_id as usize];
+        let coordinates = face_vertices
+            .iter()
+            .map(|&vertex| texinfo.texture_coordinates(vertex))
+            .collect();
+        (texinfo.surface_extents(face_vertices), coordinates)
+    }
+}
@@ -0,0 +1,146 @@
+use super::{Bsp, BspHeader, Face, SurfaceExtents};
+
+/// The standard animated light styles, as set by worldspawn in world.qc.
+///
+/// Each letter is a brightness from "a" (dark) to "z" (double bright), "m" being normal,
+/// played at 10 letters per second. Styles 32 to 62 are switchable lights set by QuakeC.
+pub const LIGHT_STYLES: [&str; 12] = [
+    "m",                                                   // 0 normal
+    "mmnmmommommnonmmonqnmmo",                             // 1 flicker (first variety)
+    "abcdefghijklmnopqrstuvwxyzyxwvutsrqponmlkjihgfedcba", // 2 slow strong pulse
+    "mmmmmaaaaammmmmaaaaaabcdefgabcdefg",                  // 3 candle (first variety)
+    "mamamamamama",                                        // 4 fast strobe
+    "jklmnopqrstuvwxyzyxwvutsrqponmlkj",                   // 5 gentle pulse 1
+    "nmonqnmomnmomomno",                                   // 6 flicker (second variety)
+    "mmmaaaabcdefgmmmmaaaammmaamm",                        // 7 candle (second variety)
+    "mmmaaammmaaammmabcdefaaaammmmabcdefmmmaaaa",          // 8 candle (third variety)
+    "aaaaaaaazzzzzzzz",                                    // 9 slow strobe (fourth variety)
+    "mmamammmmammamamaaamammma",                           // 10 fluorescent flicker
+    "abcdefghijklmnopqrrqponmlkjihgfedcba",                // 11 slow pulse not fade to black
+];
+
+/// Number of light styles (MAX_LIGHTSTYLES)
+pub const MAX_LIGHTSTYLES: usize = 64;
+
+/// Lightmap of a face: one brightness (RGB for Half-Life) per luxel for each of its (up to
+/// four) styles
+#[derive(Debug, Clone)]
+pub struct Lightmap {
+    pub width: u32, // luxels, one every 16 texels plus the edge
+    pub height: u32,
+    pub channels: u32,              // bytes per luxel, 1 or 3
+    pub styles: Vec<(u8, Vec<u8>)>, // style number and its luxels
+}
+
+/// Light style patterns and their brightness over time
+#[derive(Debug, Clone)]
+pub struct LightStyles {
+    pub patterns: Vec<String>,
+}
+
+impl Bsp {
+    pub fn read_lightmaps(&self, header: &BspHeader) -> Vec<u8> {
+        let start = header.lightmaps.offset as usize;
+        let end = start + header.lightmaps.size as usize;
+        self.data[start..end].to_vec()
+    }
+}
+
+impl Face {
+    /// Returns the face lightmap from the lightmaps lump, None for unlit faces (sky, liquids).
+    ///
+    /// `channels` comes from `BspFormat::lightmap_channels`.
+    pub fn lightmap(
+        &self,
+        lightmaps: &[u8],
+        extents: &SurfaceExtents,
+        channels: u32,
+    ) -> Option<Lightmap> {
+        if self.lightmap == u32::MAX {
+            return None; // -1
+        }
+
+        let width = u32::try_from(extents.extents[0] >> 4)
+            .ok()?
+            .checked_add(1)?;
+        let height = u32::try_from(extents.extents[1] >> 4)
+            .ok()?
+            .checked_add(1)?;
+        let size = width.checked_mul(height)?.checked_mul(channels)? as usize;
+
+        let mut styles = Vec::new();
+        let mut offset = self.lightmap as usize;
+        for style in self.styles() {
+            if style == 255 {
+                break;
+            }
+            let luxels = lightmaps.get(offset..offset + size)?;
+            styles.push((style, luxels.to_vec()));
+            offset += size;
+        }
+
+        Some(Lightmap {
+            width,
+            height,
+            channels,
+            styles,
+        })
+    }
+}
+
+impl Lightmap {
+    /// Adds up the styles scaled by their current value (256 is normal), like R_BuildLightMap
+    pub fn combine(&self, style_values: &[u16; MAX_LIGHTSTYLES]) -> Vec<u8> {
+        let mut blocklights = vec![0u32; (self.width * self.height * self.channels) as usize];
+        for (style, luxels) in &self.styles {
+            let scale = style_values[*style as usize % MAX_LIGHTSTYLES] as u32;
+            for (light, &luxel) in blocklights.iter_mut().zip(luxels) {
+                *light += luxel as u32 * scale;
+            }
+        }
+        blocklights
+            .into_iter()
+            .map(|light| (light >> 8).min(255) as u8)
+            .collect()
+    }
+}
+
+impl Default for LightStyles {
+    fn default() -> Self {
+        let mut patterns = vec![String::new(); MAX_LIGHTSTYLES];
+        for (pattern, standard) in patterns.iter_mut().zip(LIGHT_STYLES) {
+            *pattern = standard.to_string();
+        }
+        // Switchable lights start on
+        for pattern in &mut patterns[32..63] {
+            *pattern = "m".to_string();
+        }
+        LightStyles { patterns }
+    }
+}
+
+impl LightStyles {
+    pub fn new() -> Self {
+        LightStyles::default()
+    }
+
+    /// Changes a style pattern, like the lightstyle() builtin ("a" switches a light off)
+    pub fn set(&mut self, style: usize, pattern: &str) {
+        self.patterns[style % MAX_LIGHTSTYLES] = pattern.to_string();
+    }
+
+    /// Returns the value of every style at a given time, like R_AnimateLight
+    pub fn values(&self, time: f32) -> [u16; MAX_LIGHTSTYLES] {
+        let frame = (time * 10.0) as usize;
+        let mut values = [256; MAX_LIGHTSTYLES];
+        for (value, pattern) in values.iter_mut().zip(&self.patterns) {
+            let pattern = pattern.as_bytes();
+            if pattern.is_empty() {
+                continue;
+            }
+            let letter = pattern[frame % pattern.len()].saturating_sub(b'a');
+            *value = letter as u16 * 22;
+        }
+        values
+    }
+}
@@ -0,0 +1,224 @@
+use std::io::{self, Write};
+
+use glam::{Vec2, Vec3};
+
+use super::{Bsp, BspHeader, LightStyles, Lightmap, MAX_LIGHTSTYLES};
+use crate::image::IndexedImage;
+
+mod gltf;
+mod obj;
+
+/// Width of the lightmap atlas, in luxels
+const ATLAS_WIDTH: u32 = 512;
+
+/// Triangulated map geometry grouped by texture, to export to other tools.
+///
+/// Coordinates are Y up like glTF: Quake (x, y, z) becomes (x, z, -y).
+#[derive(Debug, Default)]
+pub struct MapMesh {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,          // texture coordinates, 1.0 is the texture size
+    pub lightmap_uvs: Vec<Vec2>, // coordinates in the lightmap atlas, empty if not baked
+    pub materials: Vec<MeshMaterial>,
+    pub lightmap_atlas: Option<LightmapAtlas>,
+}
+
+/// Faces sharing a texture of the miptex lump
+#[derive(Debug)]
+pub struct MeshMaterial {
+    pub name: String,
+    pub image: Option<IndexedImage>, // None for missing or external (WAD) textures
+    pub indices: Vec<u32>,           // triangles, counter clockwise
+}
+
+/// Every face lightmap packed in one RGB image
+#[derive(Debug)]
+pub struct LightmapAtlas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>, // RGB24
+}
+
+impl Bsp {
+    /// Walks every face through ledges, edges and vertices, triangulates it and computes its
+    /// texture coordinates. With `lightmaps`, the lightmaps (styles at their rest value) are
+    /// packed into an atlas and a second set of coordinates points into it.
+    pub fn build_mesh(&self, header: &BspHeader, lightmaps: bool) -> MapMesh {
+        let vertices = self.read_vertices(header);
+        let edges = self.read_edges(header);
+        let ledges = self.read_ledges(header);
+        let texinfo = self.read_texinfo(header);
+        let faces = self.read_faces(header);
+        let lightmap_data = self.read_lightmaps(header);
+        let channels = header.format().lightmap_channels();
+        let style_values = LightStyles::new().values(0.0);
+
+        let mut mesh = MapMesh::default();
+        let mut sizes = Vec::new();
+        for (i, texture) in self.read_miptex(header).into_iter().enumerate() {
+            let (name, image, size) = match texture {
+                Some(texture) => (
+                    texture.name,
+                    texture.mips.into_iter().next(),
+                    Vec2::new(texture.width as f32, texture.height as f32),
+                ),
+                None => (format!("missing{}", i), None, Vec2::splat(64.0)),
+            };
+            mesh.materials.push(MeshMaterial {
+                name,
+                image,
+                indices: Vec::new(),
+            });
+            sizes.push(size.max(Vec2::ONE));
+        }
+
+        let mut atlas = lightmaps.then(AtlasBuilder::new);
+        for face in &faces {
+            let Some(info) = texinfo.get(face.texinfo_id as usize) else {
+                continue;
+            };
+            let material = info.miptex_id as usize;
+            if material >= mesh.materials.len() {
+                continue;
+            }
+            let points = face.vertices(&ledges, &edges, &vertices);
+            if points.len() < 3 {
+                continue;
+            }
+            let (extents, coordinates) = face.texture_mapping(&texinfo, &points);
+
+            let first = mesh.positions.len() as u32;
+            for (point, st) in points.iter().zip(&coordinates) {
+                mesh.positions.push(Vec3::new(point.x, point.z, -point.y));
+                mesh.uvs.push(*st / sizes[material]);
+            }
+
+            if let Some(atlas) = atlas.as_mut() {
+                let lightmap = match info.is_special() {
+                    true => None, // sky and liquids are fully bright
+                    false => face.lightmap(&lightmap_data, &extents, channels),
+                };
+                match lightmap {
+                    Some(lightmap) => {
+                        let origin = atlas.add(&lightmap, &style_values);
+                        let mins = Vec2::new(
+                            extents.texture_mins[0] as f32,
+                            extents.texture_mins[1] as f32,
+                        );
+                        for st in &coordinates {
+                            // Luxel centres sit every 16 texels from the face mins
+                            mesh.lightmap_uvs.push(origin + (*st - mins) / 16.0 + 0.5);
+                        }
+                    }
+                    None => {
+                        mesh.lightmap_uvs
+                            .extend(std::iter::repeat_n(Vec2::splat(0.5), points.len()));
+                    }
+                }
+            }
+
+            // Quake polygons are clockwise seen from the front: fan them the other way
+            let indices = &mut mesh.materials[material].indices;
+            for i in 1..points.len() as u32 - 1 {
+                indices.extend_from_slice(&[first, first + i + 1, first + i]);
+            }
+        }
+
+        if let Some(atlas) = atlas {
+            let atlas = atlas.finish();
+            let size = Vec2::new(atlas.width as f32, atlas.height as f32);
+            for uv in mesh.lightmap_uvs.iter_mut() {
+                *uv /= size;
+            }
+            mesh.lightmap_atlas = Some(atlas);
+        }
+
+        mesh
+    }
+}
+
+impl MeshMaterial {
+    /// PNG file name of the texture, "*" is not allowed in file names everywhere
+    pub fn texture_file(&self) -> String {
+        format!("{}.png", self.name.replace('*', "#"))
+    }
+}
+
+impl LightmapAtlas {
+    pub fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&self.pixels)?;
+        png_writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Packs lightmaps row by row, left to right
+struct AtlasBuilder {
+    rows: Vec<Vec<u8>>, // RGB24 rows of ATLAS_WIDTH luxels
+    x: u32,
+    y: u32,
+    row_height: u32,
+}
+
+impl AtlasBuilder {
+    fn new() -> Self {
+        let mut atlas = AtlasBuilder {
+            rows: Vec::new(),
+            x: 1,
+            y: 0,
+            row_height: 1,
+        };
+        // Luxel (0, 0) is white, for faces without a lightmap
+        atlas.grow(1);
+        atlas.rows[0][..3].fill(255);
+        atlas
+    }
+
+    fn grow(&mut self, height: usize) {
+        while self.rows.len() < height {
+            self.rows.push(vec![0; ATLAS_WIDTH as usize * 3]);
+        }
+    }
+
+    /// Copies a lightmap in, returns the position of its first luxel
+    fn add(&mut self, lightmap: &Lightmap, style_values: &[u16; MAX_LIGHTSTYLES]) -> Vec2 {
+        let width = lightmap.width.min(ATLAS_WIDTH);
+        if self.x + width > ATLAS_WIDTH {
+            self.x = 0;
+            self.y += self.row_height;
+            self.row_height = 0;
+        }
+        let (x, y) = (self.x, self.y);
+        self.grow((y + lightmap.height) as usize);
+
+        let luxels = lightmap.combine(style_values);
+        let channels = lightmap.channels as usize;
+        for row in 0..lightmap.height {
+            for column in 0..width {
+                let source = (row * lightmap.width + column) as usize * channels;
+                let target = &mut self.rows[(y + row) as usize][(x + column) as usize * 3..][..3];
+                if channels == 3 {
+                    target.copy_from_slice(&luxels[source..source + 3]);
+                } else {
+                    target.fill(luxels[source]);
+                }
+            }
+        }
+
+        self.x += width;
+        self.row_height = self.row_height.max(lightmap.height);
+        Vec2::new(x as f32, y as f32)
+    }
+
+    fn finish(self) -> LightmapAtlas {
+        LightmapAtlas {
+            width: ATLAS_WIDTH,
+            height: self.rows.len() as u32,
+            pixels: self.rows.concat(),
+        }
+    }
+}
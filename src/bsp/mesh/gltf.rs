@@ -0,0 +1,246 @@
+use std::io::{self, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use glam::Vec3;
+
+use super::MapMesh;
+
+// glTF constants
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const NEAREST: u32 = 9728;
+
+impl MapMesh {
+    /// Writes the mesh as binary glTF (.glb), textures embedded as PNG.
+    ///
+    /// A baked lightmap atlas is the occlusion texture of every material, read with the
+    /// second set of texture coordinates. Quake textures are decoded with `palette`.
+    pub fn write_glb<W: Write>(&self, writer: &mut W, palette: &[(u8, u8, u8)]) -> io::Result<()> {
+        let mut bin = BinaryBuffer::default();
+        let mut accessors = Vec::new();
+        let mut images = Vec::new();
+        let mut materials = Vec::new();
+        let mut primitives = Vec::new();
+
+        let mut attributes = String::new();
+        if !self.positions.is_empty() {
+            let (min, max) = self.positions.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), &p| (min.min(p), max.max(p)),
+            );
+            let data: Vec<f32> = self.positions.iter().flat_map(|p| p.to_array()).collect();
+            let view = bin.add_view(&floats(&data), Some(ARRAY_BUFFER));
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+                view, FLOAT, self.positions.len(), min.x, min.y, min.z, max.x, max.y, max.z
+            ));
+            attributes.push_str(&format!(r#""POSITION":{}"#, accessors.len() - 1));
+
+            for (set, uvs) in [&self.uvs, &self.lightmap_uvs].into_iter().enumerate() {
+                if uvs.len() != self.positions.len() {
+                    continue;
+                }
+                let data: Vec<f32> = uvs.iter().flat_map(|uv| uv.to_array()).collect();
+                let view = bin.add_view(&floats(&data), Some(ARRAY_BUFFER));
+                accessors.push(format!(
+                    r#"{{"bufferView":{},"componentType":{},"count":{},"type":"VEC2"}}"#,
+                    view,
+                    FLOAT,
+                    uvs.len()
+                ));
+                attributes.push_str(&format!(r#","TEXCOORD_{}":{}"#, set, accessors.len() - 1));
+            }
+        }
+
+        let lightmap = match &self.lightmap_atlas {
+            Some(atlas) if self.lightmap_uvs.len() == self.positions.len() => {
+                let mut png = Vec::new();
+                atlas.write_png(&mut png)?;
+                images.push(bin.add_view(&png, None));
+                Some(images.len() - 1)
+            }
+            _ => None,
+        };
+
+        for material in &self.materials {
+            if material.indices.is_empty() {
+                continue;
+            }
+
+            let mut pbr = r#""metallicFactor":0,"roughnessFactor":1"#.to_string();
+            if let Some(image) = &material.image {
+                let mut png = Vec::new();
+                image.write_png(&mut png, palette)?;
+                images.push(bin.add_view(&png, None));
+                pbr.push_str(&format!(
+                    r#","baseColorTexture":{{"index":{}}}"#,
+                    images.len() - 1
+                ));
+            }
+            let mut json = format!(
+                r#"{{"name":"{}","pbrMetallicRoughness":{{{}}}"#,
+                escape(&material.name),
+                pbr
+            );
+            if let Some(lightmap) = lightmap {
+                json.push_str(&format!(
+                    r#","occlusionTexture":{{"index":{},"texCoord":1}}"#,
+                    lightmap
+                ));
+            }
+            json.push('}');
+            materials.push(json);
+
+            let indices: Vec<u8> = material
+                .indices
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect();
+            let view = bin.add_view(&indices, Some(ELEMENT_ARRAY_BUFFER));
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":{},"count":{},"type":"SCALAR"}}"#,
+                view,
+                UNSIGNED_INT,
+                material.indices.len()
+            ));
+            primitives.push(format!(
+                r#"{{"attributes":{{{}}},"indices":{},"material":{}}}"#,
+                attributes,
+                accessors.len() - 1,
+                materials.len() - 1
+            ));
+        }
+
+        // One texture per image, all with the same nearest filtered sampler
+        let textures: Vec<String> = (0..images.len())
+            .map(|i| format!(r#"{{"sampler":0,"source":{}}}"#, i))
+            .collect();
+        let images: Vec<String> = images
+            .iter()
+            .map(|view| format!(r#"{{"bufferView":{},"mimeType":"image/png"}}"#, view))
+            .collect();
+        let (meshes, nodes, scene_nodes) = if primitives.is_empty() {
+            (String::new(), String::new(), "")
+        } else {
+            (
+                format!(r#"[{{"primitives":[{}]}}]"#, primitives.join(",")),
+                r#"[{"mesh":0}]"#.to_string(),
+                "0",
+            )
+        };
+
+        let mut json = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"quake"}},"scene":0,"scenes":[{{"nodes":[{}]}}]"#,
+            scene_nodes
+        );
+        if !nodes.is_empty() {
+            json.push_str(&format!(r#","nodes":{},"meshes":{}"#, nodes, meshes));
+        }
+        for (name, items) in [
+            ("accessors", &accessors),
+            ("materials", &materials),
+            ("textures", &textures),
+            ("images", &images),
+            ("bufferViews", &bin.views),
+        ] {
+            if !items.is_empty() {
+                json.push_str(&format!(r#","{}":[{}]"#, name, items.join(",")));
+            }
+        }
+        if !textures.is_empty() {
+            json.push_str(&format!(
+                r#","samplers":[{{"magFilter":{},"minFilter":{}}}]"#,
+                NEAREST, NEAREST
+            ));
+        }
+        if !bin.data.is_empty() {
+            json.push_str(&format!(
+                r#","buffers":[{{"byteLength":{}}}]"#,
+                bin.data.len()
+            ));
+        }
+        json.push('}');
+
+        write_glb_chunks(writer, json.into_bytes(), bin.data)
+    }
+}
+
+/// The BIN chunk and the buffer views pointing into it
+#[derive(Default)]
+struct BinaryBuffer {
+    data: Vec<u8>,
+    views: Vec<String>,
+}
+
+impl BinaryBuffer {
+    /// Appends data aligned to 4 bytes, returns its buffer view index
+    fn add_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        let mut view = format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{}"#,
+            self.data.len(),
+            data.len()
+        );
+        if let Some(target) = target {
+            view.push_str(&format!(r#","target":{}"#, target));
+        }
+        view.push('}');
+        self.data.extend_from_slice(data);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+}
+
+/// Header, JSON chunk padded with spaces, BIN chunk padded with zeros
+fn write_glb_chunks<W: Write>(
+    writer: &mut W,
+    mut json: Vec<u8>,
+    mut bin: Vec<u8>,
+) -> io::Result<()> {
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+    let bin_chunk = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let length = 12 + 8 + json.len() + bin_chunk;
+    if length > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "glTF file larger than 4GB",
+        ));
+    }
+
+    writer.write_all(b"glTF")?;
+    writer.write_u32::<LittleEndian>(2)?;
+    writer.write_u32::<LittleEndian>(length as u32)?;
+    writer.write_u32::<LittleEndian>(json.len() as u32)?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+    if !bin.is_empty() {
+        writer.write_u32::<LittleEndian>(bin.len() as u32)?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&bin)?;
+    }
+    Ok(())
+}
+
+fn floats(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// Escapes a texture name for a JSON string
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
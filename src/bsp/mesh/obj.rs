@@ -0,0 +1,48 @@
+use std::io::{self, Write};
+
+use super::MapMesh;
+
+impl MapMesh {
+    /// Writes the mesh as Wavefront OBJ, one group of faces per material of `mtl_file`.
+    ///
+    /// OBJ only has one set of texture coordinates, lightmaps are only exported to glTF.
+    pub fn write_obj<W: Write>(&self, writer: &mut W, mtl_file: &str) -> io::Result<()> {
+        writeln!(writer, "mtllib {}", mtl_file)?;
+        for position in &self.positions {
+            writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+        }
+        // OBJ texture coordinates go up
+        for uv in &self.uvs {
+            writeln!(writer, "vt {} {}", uv.x, 1.0 - uv.y)?;
+        }
+
+        for material in &self.materials {
+            if material.indices.is_empty() {
+                continue;
+            }
+            writeln!(writer, "usemtl {}", material.name)?;
+            for triangle in material.indices.chunks_exact(3) {
+                // 1-based, same index for the position and its coordinates
+                let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+                writeln!(writer, "f {}/{} {}/{} {}/{}", a, a, b, b, c, c)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the materials, textures are read from `MeshMaterial::texture_file` next to it
+    pub fn write_mtl<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for material in &self.materials {
+            if material.indices.is_empty() {
+                continue;
+            }
+            writeln!(writer, "newmtl {}", material.name)?;
+            writeln!(writer, "Kd 1 1 1")?;
+            if material.image.is_some() {
+                writeln!(writer, "map_Kd {}", material.texture_file())?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{Bsp, BspFormat, BspHeader};
+use crate::image::MipTex;
+
+/// How a texture is drawn, recognised from its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureKind {
+    Normal,
+    Sky,                   // "sky..." two scrolling layers
+    Liquid,                // "*water", "*lava", "*slime", "*teleport"... warped
+    Animated(u8),          // "+0name".."+9name", frame 0 to 9
+    AlternateAnimated(u8), // "+aname".."+jname", shown when the entity is triggered
+}
+
+/// Frames of an animated texture, as texture indices in the miptex lump
+#[derive(Debug, Clone)]
+pub struct TextureAnimation {
+    pub frames: Vec<usize>,
+    pub alternate_frames: Vec<usize>,
+}
+
+impl Bsp {
+    /// Returns every texture of the miptex lump, None where the offset is -1 (missing texture).
+    ///
+    /// Half-Life textures come with their own palette.
+    pub fn read_miptex(&self, header: &BspHeader) -> Vec<Option<MipTex>> {
+        let start = header.miptex.offset as usize;
+        let end = start + header.miptex.size as usize;
+        let lump = &self.data[start..end];
+
+        if lump.is_empty() {
+            return Vec::new();
+        }
+
+        let mut cursor = Cursor::new(lump);
+        let texture_number = cursor.read_i32::<LittleEndian>().unwrap();
+
+        let mut textures = Vec::new();
+        for _ in 0..texture_number {
+            let offset = cursor.read_i32::<LittleEndian>().unwrap();
+            if offset < 0 {
+                textures.push(None);
+                continue;
+            }
+            let data = &lump[offset as usize..];
+            let miptex = if header.format() == BspFormat::Bsp30 {
+                MipTex::from_bytes_with_palette(data)
+            } else {
+                MipTex::from_bytes(data)
+            }
+            .expect("Failed to read miptex");
+            textures.push(Some(miptex));
+        }
+
+        textures
+    }
+}
+
+impl TextureKind {
+    pub fn from_name(name: &str) -> Self {
+        let bytes = name.as_bytes();
+        if bytes.first() == Some(&b'*') {
+            return TextureKind::Liquid;
+        }
+        if name.len() >= 3 && name[..3].eq_ignore_ascii_case("sky") {
+            return TextureKind::Sky;
+        }
+        if bytes.first() == Some(&b'+') && bytes.len() > 1 {
+            match bytes[1].to_ascii_lowercase() {
+                c @ b'0'..=b'9' => return TextureKind::Animated(c - b'0'),
+                c @ b'a'..=b'j' => return TextureKind::AlternateAnimated(c - b'a'),
+                _ => {}
+            }
+        }
+        TextureKind::Normal
+    }
+}
+
+/// Links every animated texture to the frames of its sequence, like Mod_LoadTextures.
+///
+/// "+0lava" .. "+3lava" and "+alava" .. "+blava" share the base name "lava".
+pub fn texture_animations(textures: &[Option<MipTex>]) -> Vec<Option<TextureAnimation>> {
+    let base_name = |name: &str| name.get(2..).unwrap_or("").to_ascii_lowercase();
+
+    textures
+        .iter()
+        .map(|texture| {
+            let texture = texture.as_ref()?;
+            if !matches!(
+                TextureKind::from_name(&texture.name),
+                TextureKind::Animated(_) | TextureKind::AlternateAnimated(_)
+            ) {
+                return None;
+            }
+            let base = base_name(&texture.name);
+
+            let mut frames: Vec<(u8, usize)> = Vec::new();
+            let mut alternate_frames: Vec<(u8, usize)> = Vec::new();
+            for (i, other) in textures.iter().enumerate() {
+                let Some(other) = other else {
+                    continue;
+                };
+                if base_name(&other.name) != base {
+                    continue;
+                }
+                match TextureKind::from_name(&other.name) {
+                    TextureKind::Animated(frame) => frames.push((frame, i)),
+                    TextureKind::AlternateAnimated(frame) => alternate_frames.push((frame, i)),
+                    _ => {}
+                }
+            }
+            frames.sort();
+            alternate_frames.sort();
+
+            Some(TextureAnimation {
+                frames: frames.into_iter().map(|(_, i)| i).collect(),
+                alternate_frames: alternate_frames.into_iter().map(|(_, i)| i).collect(),
+            })
+        })
+        .collect()
+}
+
+impl TextureAnimation {
+    /// Returns the texture index to draw at a given time, animations run at 5 frames per second
+    pub fn frame_at(&self, time: f32, alternate: bool) -> usize {
+        // A sequence with only alternate frames always shows those
+        let frames = if (alternate || self.frames.is_empty()) && !self.alternate_frames.is_empty() {
+            &self.alternate_frames
+        } else {
+            &self.frames
+        };
+        frames[(time * 5.0) as usize % frames.len()]
+    }
+}
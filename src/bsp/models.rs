@@ -0,0 +1,60 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use glam::Vec3;
+
+use super::{read_vec3, Bsp, BspHeader};
+
+/// Brush model: model 0 is the world, "*1", "*2"... are doors, plats, triggers...
+#[derive(Debug, Clone)]
+pub struct BspModel {
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub origin: Vec3,
+    pub headnodes: [i32; 4], // BSP tree, then the clipnode hulls 1 to 3
+    pub visleafs: u32,       // leaves covered by the PVS rows (world only)
+    pub face_id: u32,
+    pub face_num: u32,
+}
+
+impl Bsp {
+    pub fn read_models(&self, header: &BspHeader) -> Vec<BspModel> {
+        let start = header.models.offset as usize;
+        let end = start + header.models.size as usize;
+
+        let mut models = Vec::new();
+        let mut cursor = Cursor::new(&self.data[start..end]);
+
+        while (cursor.position() as usize) + 64 <= header.models.size as usize {
+            let mins = read_vec3(&mut cursor);
+            let maxs = read_vec3(&mut cursor);
+            let origin = read_vec3(&mut cursor);
+            let mut headnodes = [0i32; 4];
+            for headnode in headnodes.iter_mut() {
+                *headnode = cursor.read_i32::<LittleEndian>().unwrap();
+            }
+            let visleafs = cursor.read_u32::<LittleEndian>().unwrap();
+            let face_id = cursor.read_u32::<LittleEndian>().unwrap();
+            let face_num = cursor.read_u32::<LittleEndian>().unwrap();
+
+            models.push(BspModel {
+                mins,
+                maxs,
+                origin,
+                headnodes,
+                visleafs,
+                face_id,
+                face_num,
+            });
+        }
+
+        models
+    }
+}
+
+impl BspModel {
+    /// Returns the indices of the model faces
+    pub fn faces(&self) -> std::ops::Range<u32> {
+        self.face_id..self.face_id + self.face_num
+    }
+}
@@ -0,0 +1,199 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use glam::Vec3;
+
+use super::{read_bounds, read_child, read_index, Bsp, BspHeader, Plane};
+
+// Leaf contents
+pub const CONTENTS_EMPTY: i32 = -1;
+pub const CONTENTS_SOLID: i32 = -2;
+pub const CONTENTS_WATER: i32 = -3;
+pub const CONTENTS_SLIME: i32 = -4;
+pub const CONTENTS_LAVA: i32 = -5;
+pub const CONTENTS_SKY: i32 = -6;
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub plane_id: u32,
+    pub children: [i32; 2], // front and back, negative for leaves: -1 - leaf index
+    pub mins: Vec3,         // bounding box, for frustum culling
+    pub maxs: Vec3,
+    pub face_id: u32, // faces lying on the node plane
+    pub face_num: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Leaf {
+    pub contents: i32, // CONTENTS_*
+    pub visofs: i32,   // offset in the visibility lump, -1 if none
+    pub mins: Vec3,
+    pub maxs: Vec3,
+    pub mark_surface_id: u32, // range of the mark surfaces lump
+    pub mark_surface_num: u32,
+    pub ambient_level: [u8; 4], // water, sky, slime and lava sounds
+}
+
+/// What a node child points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeChild {
+    Node(usize),
+    Leaf(usize),
+}
+
+/// Nodes, leaves and planes of the map, to find where a point lies and what it can see
+pub struct BspTree {
+    pub planes: Vec<Plane>,
+    pub nodes: Vec<Node>,
+    pub leaves: Vec<Leaf>,       // leaf 0 is the solid leaf outside the map
+    pub mark_surfaces: Vec<u32>, // face indices, listed by leaf
+    pub visibility: Vec<u8>,     // compressed PVS rows, see leaf_pvs
+    pub visleafs: usize,         // world leaves covered by the PVS rows
+}
+
+impl Bsp {
+    pub fn read_nodes(&self, header: &BspHeader) -> Vec<Node> {
+        let start = header.nodes.offset as usize;
+        let end = start + header.nodes.size as usize;
+
+        let format = header.format();
+        let record_size = format.record_size("nodes");
+
+        let mut nodes = Vec::new();
+        let mut cursor = Cursor::new(&self.data[start..end]);
+
+        while (cursor.position() as usize) + record_size <= header.nodes.size as usize {
+            let plane_id = cursor.read_u32::<LittleEndian>().unwrap();
+            let front = read_child(&mut cursor, format);
+            let back = read_child(&mut cursor, format);
+            let mins = read_bounds(&mut cursor, format);
+            let maxs = read_bounds(&mut cursor, format);
+            let face_id = read_index(&mut cursor, format);
+            let face_num = read_index(&mut cursor, format);
+
+            nodes.push(Node {
+                plane_id,
+                children: [front, back],
+                mins,
+                maxs,
+                face_id,
+                face_num,
+            });
+        }
+
+        nodes
+    }
+
+    pub fn read_leaves(&self, header: &BspHeader) -> Vec<Leaf> {
+        let start = header.leaves.offset as usize;
+        let end = start + header.leaves.size as usize;
+
+        let format = header.format();
+        let record_size = format.record_size("leaves");
+
+        let mut leaves = Vec::new();
+        let mut cursor = Cursor::new(&self.data[start..end]);
+
+        while (cursor.position() as usize) + record_size <= header.leaves.size as usize {
+            let contents = cursor.read_i32::<LittleEndian>().unwrap();
+            let visofs = cursor.read_i32::<LittleEndian>().unwrap();
+            let mins = read_bounds(&mut cursor, format);
+            let maxs = read_bounds(&mut cursor, format);
+            let mark_surface_id = read_index(&mut cursor, format);
+            let mark_surface_num = read_index(&mut cursor, format);
+            let mut ambient_level = [0u8; 4];
+            for level in ambient_level.iter_mut() {
+                *level = cursor.read_u8().unwrap();
+            }
+
+            leaves.push(Leaf {
+                contents,
+                visofs,
+                mins,
+                maxs,
+                mark_surface_id,
+                mark_surface_num,
+                ambient_level,
+            });
+        }
+
+        leaves
+    }
+
+    /// Reads the mark surfaces (lfaces) lump: face indices referenced by the leaves
+    pub fn read_mark_surfaces(&self, header: &BspHeader) -> Vec<u32> {
+        let start = header.lfaces.offset as usize;
+        let end = start + header.lfaces.size as usize;
+
+        let format = header.format();
+        let record_size = format.record_size("lfaces");
+
+        let mut mark_surfaces = Vec::new();
+        let mut cursor = Cursor::new(&self.data[start..end]);
+
+        while (cursor.position() as usize) + record_size <= header.lfaces.size as usize {
+            mark_surfaces.push(read_index(&mut cursor, format));
+        }
+
+        mark_surfaces
+    }
+
+    pub fn read_tree(&self, header: &BspHeader) -> BspTree {
+        let leaves = self.read_leaves(header);
+        let visleafs = match self.read_models(header).first() {
+            Some(world) => world.visleafs as usize,
+            None => leaves.len().saturating_sub(1),
+        };
+        BspTree {
+            planes: self.read_planes(header),
+            nodes: self.read_nodes(header),
+            leaves,
+            mark_surfaces: self.read_mark_surfaces(header),
+            visibility: self.read_visibility(header),
+            visleafs,
+        }
+    }
+}
+
+impl Node {
+    pub fn child(&self, side: usize) -> NodeChild {
+        let child = self.children[side];
+        if child < 0 {
+            NodeChild::Leaf((-1 - child) as usize)
+        } else {
+            NodeChild::Node(child as usize)
+        }
+    }
+}
+
+impl BspTree {
+    /// Returns the index of the leaf containing a point, like Mod_PointInLeaf
+    pub fn point_in_leaf(&self, point: Vec3) -> usize {
+        self.point_in_leaf_from(0, point)
+    }
+
+    /// Same from another head node, such as the one of a brush model
+    pub fn point_in_leaf_from(&self, headnode: usize, point: Vec3) -> usize {
+        let mut node = headnode;
+        loop {
+            let n = &self.nodes[node];
+            let side = if self.planes[n.plane_id as usize].distance(point) > 0.0 {
+                0
+            } else {
+                1
+            };
+            match n.child(side) {
+                NodeChild::Node(child) => node = child,
+                NodeChild::Leaf(leaf) => return leaf,
+            }
+        }
+    }
+
+    /// Returns the faces of a leaf
+    pub fn leaf_faces(&self, leaf: usize) -> &[u32] {
+        let leaf = &self.leaves[leaf];
+        let start = leaf.mark_surface_id as usize;
+        let end = start + leaf.mark_surface_num as usize;
+        self.mark_surfaces.get(start..end).unwrap_or(&[])
+    }
+}
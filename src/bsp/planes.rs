@@ -4,7 +4,7 @@ use glam::Vec3;
 
 use super::{Bsp, BspHeader};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Plane {
     pub normal: Vec3,
     pub dist: f32,
@@ -49,3 +49,14 @@ impl Bsp {
         planes
     }
 }
+
+impl Plane {
+    /// Signed distance of a point to the plane, positive in front
+    pub fn distance(&self, point: Vec3) -> f32 {
+        // Axial planes (types 0 to 2) only need one coordinate
+        match self.plane_type {
+            0..=2 => point[self.plane_type as usize] - self.dist,
+            _ => self.normal.dot(point) - self.dist,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use glam::{Vec2, Vec3};
+
+use super::{read_vec3, Bsp, BspHeader};
+
+/// Sky or liquid: no lightmap, the texture is warped or scrolled instead
+pub const TEX_SPECIAL: u32 = 1;
+
+#[derive(Debug)]
+pub struct TexInfo {
+    pub s_vector: Vec3,
+    pub s_offset: f32,
+    pub t_vector: Vec3,
+    pub t_offset: f32,
+    pub miptex_id: u32,
+    pub flags: u32,
+}
+
+/// Texture space bounds of a face, snapped to the 16 texel lightmap grid
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceExtents {
+    pub texture_mins: [i32; 2], // s and t of the top left luxel
+    pub extents: [i32; 2],      // width and height in texels
+}
+
+impl Bsp {
+    pub fn read_texinfo(&self, header: &BspHeader) -> Vec<TexInfo> {
+        let start = header.texinfo.offset as usize;
+        let end = start + header.texinfo.size as usize;
+
+        let mut texinfo = Vec::new();
+        let mut cursor = Cursor::new(&self.data[start..end]);
+
+        while (cursor.position() as usize) + 40 <= header.texinfo.size as usize {
+            let s_vector = read_vec3(&mut cursor);
+            let s_offset = cursor.read_f32::<LittleEndian>().unwrap();
+            let t_vector = read_vec3(&mut cursor);
+            let t_offset = cursor.read_f32::<LittleEndian>().unwrap();
+            let miptex_id = cursor.read_u32::<LittleEndian>().unwrap();
+            let flags = cursor.read_u32::<LittleEndian>().unwrap();
+
+            texinfo.push(TexInfo {
+                s_vector,
+                s_offset,
+                t_vector,
+                t_offset,
+                miptex_id,
+                flags,
+            });
+        }
+
+        texinfo
+    }
+}
+
+impl TexInfo {
+    pub fn is_special(&self) -> bool {
+        self.flags & TEX_SPECIAL != 0
+    }
+
+    /// Returns the s and t texel coordinates of a point
+    pub fn texture_coordinates(&self, point: Vec3) -> Vec2 {
+        Vec2::new(
+            point.dot(self.s_vector) + self.s_offset,
+            point.dot(self.t_vector) + self.t_offset,
+        )
+    }
+
+    /// Returns the texture space extents of a face from its vertices, like CalcSurfaceExtents
+    pub fn surface_extents(&self, points: &[Vec3]) -> SurfaceExtents {
+        let mut mins = [f64::MAX; 2];
+        let mut maxs = [f64::MIN; 2];
+
+        for point in points {
+            // Double precision, single precision puts some faces one luxel off
+            for (i, (vector, offset)) in [
+                (self.s_vector, self.s_offset),
+                (self.t_vector, self.t_offset),
+            ]
+            .iter()
+            .enumerate()
+            {
+                let value = point.as_dvec3().dot(vector.as_dvec3()) + *offset as f64;
+                mins[i] = mins[i].min(value);
+                maxs[i] = maxs[i].max(value);
+            }
+        }
+
+        let mut texture_mins = [0; 2];
+        let mut extents = [0; 2];
+        for i in 0..2 {
+            let bmin = (mins[i] / 16.0).floor() as i32;
+            let bmax = (maxs[i] / 16.0).ceil() as i32;
+            // Saturated for hostile coordinates, Bsp::load rejects such extents
+            texture_mins[i] = bmin.saturating_mul(16);
+            extents[i] = bmax.saturating_sub(bmin).saturating_mul(16);
+        }
+
+        SurfaceExtents {
+            texture_mins,
+            extents,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use super::{Bsp, BspError, BspFormat, BspHeader};
+use crate::image::MipTex;
+
+/// Size of the version and the 15 lump entries
+pub(super) const HEADER_SIZE: usize = 4 + 15 * 8;
+
+/// Largest texture extents of a lit face, in texels (17 luxels with the edge)
+const MAX_SURFACE_EXTENTS: i32 = 256;
+
+impl Bsp {
+    /// Checks that every lump lies in the file and that records only refer to existing ones,
+    /// so the readers and tree walks cannot go out of bounds
+    pub(super) fn validate(&self, header: &BspHeader) -> Result<(), BspError> {
+        let format = header.format();
+        for (lump, entry) in header.lumps() {
+            let end = entry.offset as u64 + entry.size as u64;
+            if end > self.data.len() as u64 {
+                return Err(BspError::LumpOutOfBounds {
+                    lump,
+                    offset: entry.offset,
+                    size: entry.size,
+                    file_size: self.data.len(),
+                });
+            }
+            let record_size = format.record_size(lump);
+            if !(entry.size as usize).is_multiple_of(record_size) {
+                return Err(BspError::BadLumpSize {
+                    lump,
+                    size: entry.size,
+                    record_size,
+                });
+            }
+        }
+
+        self.read_entities(header)
+            .map_err(|error| BspError::InvalidLump {
+                lump: "entities",
+                message: error.to_string(),
+            })?;
+        let textures = self.validate_miptex(header)?;
+
+        let planes = self.read_planes(header).len();
+        let vertices = self.read_vertices(header);
+        let edges = self.read_edges(header);
+        let ledges = self.read_ledges(header);
+        let texinfo = self.read_texinfo(header);
+        let faces = self.read_faces(header);
+        let nodes = self.read_nodes(header);
+        let leaves = self.read_leaves(header);
+        let mark_surfaces = self.read_mark_surfaces(header);
+        let clipnodes = self.read_clipnodes(header);
+        let models = self.read_models(header);
+        let lightmaps = header.lightmaps.size as usize;
+        let visibility = header.visilist.size as usize;
+
+        for (i, edge) in edges.iter().enumerate() {
+            check_index(
+                "edges",
+                i,
+                "start vertex",
+                edge.start_vertex as i64,
+                vertices.len(),
+            )?;
+            check_index(
+                "edges",
+                i,
+                "end vertex",
+                edge.end_vertex as i64,
+                vertices.len(),
+            )?;
+        }
+        for (i, &ledge) in ledges.iter().enumerate() {
+            check_index("ledges", i, "edge", (ledge as i64).abs(), edges.len())?;
+        }
+        if textures > 0 {
+            for (i, info) in texinfo.iter().enumerate() {
+                check_index("texinfo", i, "miptex", info.miptex_id as i64, textures)?;
+            }
+        }
+        for (i, face) in faces.iter().enumerate() {
+            check_index("faces", i, "plane", face.plane_id as i64, planes)?;
+            check_index("faces", i, "texinfo", face.texinfo_id as i64, texinfo.len())?;
+            check_range(
+                "faces",
+                i,
+                "ledges",
+                face.ledge_id,
+                face.ledge_num,
+                ledges.len(),
+            )?;
+            if face.lightmap != u32::MAX {
+                check_index("faces", i, "lightmap", face.lightmap as i64, lightmaps)?;
+            }
+            // Like "Bad surface extents" in CalcSurfaceExtents, sky and liquids are unlit
+            let info = &texinfo[face.texinfo_id as usize];
+            if !info.is_special() {
+                let points = face.vertices(&ledges, &edges, &vertices);
+                let extents = info.surface_extents(&points).extents;
+                if extents
+                    .iter()
+                    .any(|extent| !(0..=MAX_SURFACE_EXTENTS).contains(extent))
+                {
+                    return Err(BspError::InvalidLump {
+                        lump: "faces",
+                        message: format!(
+                            "face {} has surface extents {}x{}, more than {}",
+                            i, extents[0], extents[1], MAX_SURFACE_EXTENTS
+                        ),
+                    });
+                }
+            }
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            check_index("nodes", i, "plane", node.plane_id as i64, planes)?;
+            check_range(
+                "nodes",
+                i,
+                "faces",
+                node.face_id,
+                node.face_num,
+                faces.len(),
+            )?;
+            for child in node.children {
+                if child >= 0 {
+                    check_index("nodes", i, "child node", child as i64, nodes.len())?;
+                } else {
+                    check_index("nodes", i, "child leaf", (-1 - child) as i64, leaves.len())?;
+                }
+            }
+        }
+        for (i, leaf) in leaves.iter().enumerate() {
+            check_range(
+                "leaves",
+                i,
+                "mark surfaces",
+                leaf.mark_surface_id,
+                leaf.mark_surface_num,
+                mark_surfaces.len(),
+            )?;
+            if leaf.visofs >= 0 {
+                check_index("leaves", i, "visibility", leaf.visofs as i64, visibility)?;
+            }
+        }
+        for (i, &face) in mark_surfaces.iter().enumerate() {
+            check_index("lfaces", i, "face", face as i64, faces.len())?;
+        }
+        for (i, clipnode) in clipnodes.iter().enumerate() {
+            check_index("clipnodes", i, "plane", clipnode.plane_id as i64, planes)?;
+            for child in clipnode.children {
+                if child >= 0 {
+                    check_index("clipnodes", i, "child", child as i64, clipnodes.len())?;
+                }
+            }
+        }
+        check_no_loop("nodes", nodes.len(), |i| nodes[i].children)?;
+        check_no_loop("clipnodes", clipnodes.len(), |i| clipnodes[i].children)?;
+        if models.is_empty() {
+            return Err(BspError::InvalidLump {
+                lump: "models",
+                message: "no world model".to_string(),
+            });
+        }
+        for (i, model) in models.iter().enumerate() {
+            check_range(
+                "models",
+                i,
+                "faces",
+                model.face_id,
+                model.face_num,
+                faces.len(),
+            )?;
+            check_index(
+                "models",
+                i,
+                "head node",
+                model.headnodes[0] as i64,
+                nodes.len(),
+            )?;
+            for &headnode in &model.headnodes[1..3] {
+                // Negative head nodes are contents, like clipnode children
+                if headnode >= 0 {
+                    check_index("models", i, "clip node", headnode as i64, clipnodes.len())?;
+                }
+            }
+            check_index("models", i, "visleafs", model.visleafs as i64, leaves.len())?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the texture offsets and the mip levels they point to, returns the texture count
+    fn validate_miptex(&self, header: &BspHeader) -> Result<usize, BspError> {
+        let start = header.miptex.offset as usize;
+        let lump = &self.data[start..start + header.miptex.size as usize];
+        if lump.is_empty() {
+            return Ok(0);
+        }
+
+        let invalid = |message: String| BspError::InvalidLump {
+            lump: "miptex",
+            message,
+        };
+        let count = read_i32(lump, 0).ok_or_else(|| invalid("truncated".to_string()))?;
+        if count < 0 || (count as usize + 1) * 4 > lump.len() {
+            return Err(invalid(format!("{} textures do not fit", count)));
+        }
+        for i in 0..count as usize {
+            let offset = read_i32(lump, 4 + i * 4).unwrap();
+            if offset < 0 {
+                continue; // missing texture
+            }
+            let data = lump
+                .get(offset as usize..)
+                .ok_or_else(|| invalid(format!("texture {} lies outside the lump", i)))?;
+            let miptex = if header.format() == BspFormat::Bsp30 {
+                MipTex::from_bytes_with_palette(data)
+            } else {
+                MipTex::from_bytes(data)
+            };
+            miptex.map_err(|error| invalid(format!("texture {}: {}", i, error)))?;
+        }
+        Ok(count as usize)
+    }
+}
+
+fn read_i32(data: &[u8], offset: usize) -> Option<i32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(i32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn check_index(
+    lump: &'static str,
+    record: usize,
+    field: &'static str,
+    index: i64,
+    count: usize,
+) -> Result<(), BspError> {
+    if index < 0 || index as u64 >= count as u64 {
+        return Err(BspError::BadIndex {
+            lump,
+            record,
+            field,
+            index,
+            count,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that no node can be reached again from its own children, which would hang the
+/// tree walks. Depth first with an explicit stack, deep trees cannot overflow it. Children
+/// must already be in range.
+fn check_no_loop(
+    lump: &'static str,
+    count: usize,
+    children: impl Fn(usize) -> [i32; 2],
+) -> Result<(), BspError> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit {
+        New,
+        InProgress, // on the current path from the root
+        Done,
+    }
+
+    let mut visits = vec![Visit::New; count];
+    for root in 0..count {
+        if visits[root] != Visit::New {
+            continue;
+        }
+        visits[root] = Visit::InProgress;
+        let mut stack = vec![(root, 0)]; // node and next side to look at
+        while let Some(&(node, side)) = stack.last() {
+            if side == 2 {
+                visits[node] = Visit::Done;
+                stack.pop();
+                continue;
+            }
+            stack.last_mut().unwrap().1 += 1;
+
+            let child = children(node)[side];
+            if child < 0 {
+                continue; // leaf or contents
+            }
+            match visits[child as usize] {
+                Visit::New => {
+                    visits[child as usize] = Visit::InProgress;
+                    stack.push((child as usize, 0));
+                }
+                Visit::InProgress => {
+                    return Err(BspError::BadIndex {
+                        lump,
+                        record: node,
+                        field: "child node (loops back to a parent)",
+                        index: child as i64,
+                        count,
+                    });
+                }
+                Visit::Done => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that first .. first + num lies within count records
+fn check_range(
+    lump: &'static str,
+    record: usize,
+    field: &'static str,
+    first: u32,
+    num: u32,
+    count: usize,
+) -> Result<(), BspError> {
+    let end = first as u64 + num as u64;
+    if end > count as u64 {
+        return Err(BspError::BadIndex {
+            lump,
+            record,
+            field,
+            index: end as i64 - 1,
+            count,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::bsp::writer::tests::small_map;
+    use crate::bsp::{BspWriter, Node};
+
+    #[test]
+    fn huge_surface_extents_are_rejected() {
+        let bsp = Bsp::load(small_map()).unwrap();
+        let mut texinfo = bsp.read_texinfo(&bsp.read_header());
+        texinfo[0].s_vector = Vec3::new(0.0, 1e30, 0.0);
+        let mut writer = BspWriter::from_bsp(&bsp);
+        writer.set_texinfo(&texinfo);
+
+        assert!(matches!(
+            Bsp::load(writer.to_bytes().unwrap()),
+            Err(BspError::InvalidLump { lump: "faces", .. })
+        ));
+    }
+
+    #[test]
+    fn cyclic_trees_are_rejected() {
+        let bsp = Bsp::load(small_map()).unwrap();
+        let mut nodes = bsp.read_nodes(&bsp.read_header());
+        nodes[0].children[0] = 0;
+        let mut writer = BspWriter::from_bsp(&bsp);
+        writer.set_nodes(&nodes).unwrap();
+
+        assert!(matches!(
+            Bsp::load(writer.to_bytes().unwrap()),
+            Err(BspError::BadIndex { lump: "nodes", .. })
+        ));
+    }
+
+    #[test]
+    fn children_may_come_before_their_parent() {
+        let bsp = Bsp::load(small_map()).unwrap();
+        let header = bsp.read_header();
+        let mut nodes = bsp.read_nodes(&header);
+        nodes.push(Node {
+            children: [0, -1],
+            ..nodes[0].clone()
+        });
+        let mut models = bsp.read_models(&header);
+        models[0].headnodes[0] = 1;
+        let mut writer = BspWriter::from_bsp(&bsp);
+        writer.set_nodes(&nodes).unwrap();
+        writer.set_models(&models);
+
+        assert!(Bsp::load(writer.to_bytes().unwrap()).is_ok());
+    }
+}
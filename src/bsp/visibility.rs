@@ -0,0 +1,112 @@
+use super::{Bsp, BspHeader, BspTree};
+
+impl Bsp {
+    /// Reads the visibility lump: the compressed PVS rows of every leaf
+    pub fn read_visibility(&self, header: &BspHeader) -> Vec<u8> {
+        let start = header.visilist.offset as usize;
+        let end = start + header.visilist.size as usize;
+        self.data[start..end].to_vec()
+    }
+}
+
+/// Decompresses one PVS row, like Mod_DecompressVis.
+///
+/// Bit n stands for leaf n + 1 (leaf 0 is never visible). A zero byte is followed by a
+/// count of zero bytes to repeat; other bytes are copied as is. Without visibility data
+/// every leaf is visible.
+pub fn decompress_vis(visibility: &[u8], offset: i32, leaf_count: usize) -> Vec<u8> {
+    let row = leaf_count.div_ceil(8);
+    if offset < 0 || offset as usize >= visibility.len() {
+        return vec![0xff; row];
+    }
+
+    let mut pvs = Vec::with_capacity(row);
+    let mut input = visibility[offset as usize..].iter();
+    while pvs.len() < row {
+        match input.next() {
+            Some(0) => {
+                let count = input.next().copied().unwrap_or(0) as usize;
+                pvs.resize((pvs.len() + count).min(row), 0);
+            }
+            Some(&byte) => pvs.push(byte),
+            None => pvs.resize(row, 0), // truncated row
+        }
+    }
+    pvs
+}
+
+impl BspTree {
+    /// Returns the decompressed PVS row of a leaf
+    pub fn leaf_pvs(&self, leaf: usize) -> Vec<u8> {
+        let offset = match leaf {
+            0 => -1, // outside the map, show everything
+            _ => self.leaves[leaf].visofs,
+        };
+        decompress_vis(&self.visibility, offset, self.visleafs)
+    }
+
+    /// Returns the leaves potentially visible from a leaf
+    pub fn visible_leaves(&self, leaf: usize) -> Vec<usize> {
+        let pvs = self.leaf_pvs(leaf);
+        (0..self.visleafs)
+            .filter(|&i| pvs[i >> 3] & (1 << (i & 7)) != 0)
+            .map(|i| i + 1)
+            .collect()
+    }
+
+    /// Returns the faces of every leaf potentially visible from a leaf, each face once
+    pub fn visible_faces(&self, leaf: usize) -> Vec<u32> {
+        let mut faces: Vec<u32> = self
+            .visible_leaves(leaf)
+            .into_iter()
+            .flat_map(|leaf| self.leaf_faces(leaf).iter().copied())
+            .collect();
+        faces.sort_unstable();
+        faces.dedup();
+        faces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::bsp::Leaf;
+
+    #[test]
+    fn decompress_zero_runs() {
+        // 20 leaves give 3 byte rows
+        let visibility = [0x05, 0x00, 0x02, 0x81, 0x00, 0x09, 0x42];
+        // A zero run ending the row
+        assert_eq!(decompress_vis(&visibility, 0, 20), vec![0x05, 0, 0]);
+        // A run longer than the row is cut at its end
+        assert_eq!(decompress_vis(&visibility, 3, 20), vec![0x81, 0, 0]);
+        // Truncated data leaves the rest of the row invisible, no data shows everything
+        assert_eq!(decompress_vis(&visibility, 6, 20), vec![0x42, 0, 0]);
+        assert_eq!(decompress_vis(&visibility, -1, 20), vec![0xff; 3]);
+    }
+
+    #[test]
+    fn rows_cover_the_world_leaves_only() {
+        let leaf = |visofs| Leaf {
+            contents: -1,
+            visofs,
+            mins: Vec3::ZERO,
+            maxs: Vec3::ZERO,
+            mark_surface_id: 0,
+            mark_surface_num: 0,
+            ambient_level: [0; 4],
+        };
+        // Leaves 3 and 4 belong to a brush model, past the world visleafs
+        let tree = BspTree {
+            planes: Vec::new(),
+            nodes: Vec::new(),
+            leaves: vec![leaf(-1), leaf(0), leaf(0), leaf(-1), leaf(-1)],
+            mark_surfaces: Vec::new(),
+            visibility: vec![0xff],
+            visleafs: 2,
+        };
+        assert_eq!(tree.visible_leaves(1), vec![1, 2]);
+    }
+}
@@ -0,0 +1,615 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use glam::Vec3;
+
+use super::{
+    validate::HEADER_SIZE, write_entities, Bsp, BspFormat, BspModel, ClipNode, Edge, Entity, Face,
+    Leaf, Node, Plane, TexInfo, Vertex,
+};
+use crate::image::MipTex;
+
+// Lump numbers, in header order
+const ENTITIES: usize = 0;
+const PLANES: usize = 1;
+const MIPTEX: usize = 2;
+const VERTICES: usize = 3;
+const VISILIST: usize = 4;
+const NODES: usize = 5;
+const TEXINFO: usize = 6;
+const FACES: usize = 7;
+const LIGHTMAPS: usize = 8;
+const CLIPNODES: usize = 9;
+const LEAVES: usize = 10;
+const LFACES: usize = 11;
+const EDGES: usize = 12;
+const LEDGES: usize = 13;
+const MODELS: usize = 14;
+
+/// Order qbsp writes the lumps in
+const QBSP_ORDER: [usize; 15] = [
+    PLANES, LEAVES, VERTICES, NODES, TEXINFO, FACES, CLIPNODES, LFACES, LEDGES, EDGES, MODELS,
+    LIGHTMAPS, VISILIST, ENTITIES, MIPTEX,
+];
+
+/// Builds a .bsp file lump by lump, from scratch or from an existing map
+///
+/// The setters of records with indices fail when one does not fit the format, like a
+/// vertex past 65535 in a BSP29 map.
+pub struct BspWriter {
+    version: u32,
+    lumps: [LumpData; 15],   // in header order
+    order: [usize; 15],      // order of the lumps in the file
+    header_padding: Vec<u8>, // bytes between the header and the first lump
+    blue_shift: bool,        // entities and planes swapped in the header
+}
+
+#[derive(Default, Clone)]
+struct LumpData {
+    data: Vec<u8>,
+    padding: Vec<u8>, // bytes up to the next lump
+}
+
+impl BspWriter {
+    /// Starts an empty map of a given version (29, 30 or a BSP2 magic)
+    pub fn new(version: u32) -> Self {
+        BspWriter {
+            version,
+            lumps: Default::default(),
+            order: QBSP_ORDER,
+            header_padding: Vec::new(),
+            blue_shift: false,
+        }
+    }
+
+    /// Copies every lump of a map, with its file order, the bytes between lumps and the
+    /// Blue Shift header order, so writing it back unmodified gives the same file
+    pub fn from_bsp(bsp: &Bsp) -> Self {
+        let header = bsp.read_header();
+        let entries = header.lumps();
+
+        let mut order: Vec<usize> = (0..15).collect();
+        // Empty lumps often share the offset of the next one, they go first
+        order.sort_by_key(|&i| (entries[i].1.offset, entries[i].1.size, i));
+
+        let mut writer = BspWriter::new(header.version);
+        for (position, &i) in order.iter().enumerate() {
+            let start = entries[i].1.offset as usize;
+            let end = start + entries[i].1.size as usize;
+            let next = match order.get(position + 1) {
+                Some(&next) => entries[next].1.offset as usize,
+                None => bsp.data.len(),
+            };
+            writer.lumps[i] = LumpData {
+                data: bsp.data.get(start..end).unwrap_or(&[]).to_vec(),
+                padding: bsp.data.get(end..next).unwrap_or(&[]).to_vec(),
+            };
+        }
+        writer.order.copy_from_slice(&order);
+        let first = entries[order[0]].1.offset as usize;
+        writer.header_padding = bsp.data.get(HEADER_SIZE..first).unwrap_or(&[]).to_vec();
+        // The header says where the planes are first when read_header swapped them back
+        let first_entry = u32::from_le_bytes(bsp.data[4..8].try_into().unwrap());
+        writer.blue_shift =
+            first_entry != header.entities.offset && first_entry == header.planes.offset;
+        writer
+    }
+
+    fn format(&self) -> BspFormat {
+        BspFormat::from_version(self.version).unwrap_or(BspFormat::Bsp29)
+    }
+
+    /// Replaces a lump, padded to 4 bytes like qbsp does
+    fn set_lump(&mut self, lump: usize, data: Vec<u8>) {
+        let padding = vec![0; (4 - data.len() % 4) % 4];
+        self.lumps[lump] = LumpData { data, padding };
+    }
+
+    pub fn set_entities(&mut self, entities: &[Entity]) {
+        self.set_entities_text(&write_entities(entities));
+    }
+
+    /// Replaces the entity lump with raw text, a null terminator is added
+    pub fn set_entities_text(&mut self, text: &str) {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        // Blue Shift maps are told apart by the entities not holding whole planes
+        if self.blue_shift && data.len().is_multiple_of(20) {
+            data.push(0);
+        }
+        self.set_lump(ENTITIES, data);
+    }
+
+    pub fn set_planes(&mut self, planes: &[Plane]) {
+        let mut data = Vec::new();
+        for plane in planes {
+            write_vec3(&mut data, plane.normal);
+            data.write_f32::<LittleEndian>(plane.dist).unwrap();
+            data.write_u32::<LittleEndian>(plane.plane_type).unwrap();
+        }
+        self.set_lump(PLANES, data);
+    }
+
+    /// Writes the textures, None for missing ones (offset -1)
+    pub fn set_miptex(&mut self, textures: &[Option<MipTex>]) {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(textures.len() as u32)
+            .unwrap();
+        let mut offset = 4 + textures.len() * 4;
+        let mut texture_data = Vec::new();
+        for texture in textures {
+            match texture {
+                Some(texture) => {
+                    let bytes = texture.to_bytes();
+                    data.write_i32::<LittleEndian>(offset as i32).unwrap();
+                    offset += bytes.len();
+                    texture_data.extend_from_slice(&bytes);
+                }
+                None => data.write_i32::<LittleEndian>(-1).unwrap(),
+            }
+        }
+        data.extend_from_slice(&texture_data);
+        self.set_lump(MIPTEX, data);
+    }
+
+    pub fn set_vertices(&mut self, vertices: &[Vertex]) {
+        let mut data = Vec::new();
+        for vertex in vertices {
+            write_vec3(&mut data, vertex.coordinates);
+        }
+        self.set_lump(VERTICES, data);
+    }
+
+    /// Replaces the compressed PVS rows, leaves keep their offsets into them
+    pub fn set_visibility(&mut self, visibility: Vec<u8>) {
+        self.set_lump(VISILIST, visibility);
+    }
+
+    pub fn set_nodes(&mut self, nodes: &[Node]) -> io::Result<()> {
+        let format = self.format();
+        let mut data = Vec::new();
+        for node in nodes {
+            data.write_u32::<LittleEndian>(node.plane_id).unwrap();
+            for child in node.children {
+                write_child(&mut data, child, format)?;
+            }
+            write_bounds(&mut data, node.mins, format)?;
+            write_bounds(&mut data, node.maxs, format)?;
+            write_index(&mut data, node.face_id, format)?;
+            write_index(&mut data, node.face_num, format)?;
+        }
+        self.set_lump(NODES, data);
+        Ok(())
+    }
+
+    pub fn set_texinfo(&mut self, texinfo: &[TexInfo]) {
+        let mut data = Vec::new();
+        for info in texinfo {
+            write_vec3(&mut data, info.s_vector);
+            data.write_f32::<LittleEndian>(info.s_offset).unwrap();
+            write_vec3(&mut data, info.t_vector);
+            data.write_f32::<LittleEndian>(info.t_offset).unwrap();
+            data.write_u32::<LittleEndian>(info.miptex_id).unwrap();
+            data.write_u32::<LittleEndian>(info.flags).unwrap();
+        }
+        self.set_lump(TEXINFO, data);
+    }
+
+    pub fn set_faces(&mut self, faces: &[Face]) -> io::Result<()> {
+        let format = self.format();
+        let mut data = Vec::new();
+        for face in faces {
+            write_index(&mut data, face.plane_id, format)?;
+            write_index(&mut data, face.side, format)?;
+            data.write_u32::<LittleEndian>(face.ledge_id).unwrap();
+            write_index(&mut data, face.ledge_num, format)?;
+            write_index(&mut data, face.texinfo_id, format)?;
+            data.extend_from_slice(&face.styles());
+            data.write_u32::<LittleEndian>(face.lightmap).unwrap();
+        }
+        self.set_lump(FACES, data);
+        Ok(())
+    }
+
+    /// Replaces the lightmaps lump, faces keep their offsets into it
+    pub fn set_lightmaps(&mut self, lightmaps: Vec<u8>) {
+        self.set_lump(LIGHTMAPS, lightmaps);
+    }
+
+    /// Removes the lightmaps: faces get no lightmap (-1) and are drawn fully bright
+    pub fn strip_lightmaps(&mut self) {
+        let record_size = self.format().record_size("faces");
+        let mut faces = std::mem::take(&mut self.lumps[FACES].data);
+        // The lightmap offset is the last field of a face
+        for face in faces.chunks_exact_mut(record_size) {
+            face[record_size - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        self.set_lump(FACES, faces);
+        self.set_lump(LIGHTMAPS, Vec::new());
+    }
+
+    pub fn set_clipnodes(&mut self, clipnodes: &[ClipNode]) -> io::Result<()> {
+        let format = self.format();
+        let mut data = Vec::new();
+        for clipnode in clipnodes {
+            data.write_u32::<LittleEndian>(clipnode.plane_id).unwrap();
+            for child in clipnode.children {
+                write_child(&mut data, child, format)?;
+            }
+        }
+        self.set_lump(CLIPNODES, data);
+        Ok(())
+    }
+
+    pub fn set_leaves(&mut self, leaves: &[Leaf]) -> io::Result<()> {
+        let format = self.format();
+        let mut data = Vec::new();
+        for leaf in leaves {
+            data.write_i32::<LittleEndian>(leaf.contents).unwrap();
+            data.write_i32::<LittleEndian>(leaf.visofs).unwrap();
+            write_bounds(&mut data, leaf.mins, format)?;
+            write_bounds(&mut data, leaf.maxs, format)?;
+            write_index(&mut data, leaf.mark_surface_id, format)?;
+            write_index(&mut data, leaf.mark_surface_num, format)?;
+            data.extend_from_slice(&leaf.ambient_level);
+        }
+        self.set_lump(LEAVES, data);
+        Ok(())
+    }
+
+    pub fn set_mark_surfaces(&mut self, mark_surfaces: &[u32]) -> io::Result<()> {
+        let format = self.format();
+        let mut data = Vec::new();
+        for &face in mark_surfaces {
+            write_index(&mut data, face, format)?;
+        }
+        self.set_lump(LFACES, data);
+        Ok(())
+    }
+
+    pub fn set_edges(&mut self, edges: &[Edge]) -> io::Result<()> {
+        let format = self.format();
+        let mut data = Vec::new();
+        for edge in edges {
+            write_index(&mut data, edge.start_vertex, format)?;
+            write_index(&mut data, edge.end_vertex, format)?;
+        }
+        self.set_lump(EDGES, data);
+        Ok(())
+    }
+
+    pub fn set_ledges(&mut self, ledges: &[i32]) {
+        let mut data = Vec::new();
+        for &ledge in ledges {
+            data.write_i32::<LittleEndian>(ledge).unwrap();
+        }
+        self.set_lump(LEDGES, data);
+    }
+
+    pub fn set_models(&mut self, models: &[BspModel]) {
+        let mut data = Vec::new();
+        for model in models {
+            write_vec3(&mut data, model.mins);
+            write_vec3(&mut data, model.maxs);
+            write_vec3(&mut data, model.origin);
+            for headnode in model.headnodes {
+                data.write_i32::<LittleEndian>(headnode).unwrap();
+            }
+            data.write_u32::<LittleEndian>(model.visleafs).unwrap();
+            data.write_u32::<LittleEndian>(model.face_id).unwrap();
+            data.write_u32::<LittleEndian>(model.face_num).unwrap();
+        }
+        self.set_lump(MODELS, data);
+    }
+
+    /// Returns the whole file: header, then the lumps in file order
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut entries = [(0u32, 0u32); 15];
+        let mut body = self.header_padding.clone();
+        for &i in &self.order {
+            let offset = HEADER_SIZE + body.len();
+            let lump = &self.lumps[i];
+            if offset + lump.data.len() > u32::MAX as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "BSP file larger than 4GB",
+                ));
+            }
+            entries[i] = (offset as u32, lump.data.len() as u32);
+            body.extend_from_slice(&lump.data);
+            body.extend_from_slice(&lump.padding);
+        }
+
+        if self.blue_shift {
+            entries.swap(ENTITIES, PLANES);
+        }
+
+        let mut data = Vec::with_capacity(HEADER_SIZE + body.len());
+        data.write_u32::<LittleEndian>(self.version)?;
+        for (offset, size) in entries {
+            data.write_u32::<LittleEndian>(offset)?;
+            data.write_u32::<LittleEndian>(size)?;
+        }
+        data.extend_from_slice(&body);
+        Ok(data)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes()?)
+    }
+
+    /// Writes the map to a file
+    pub fn save(&self, filepath: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+fn write_vec3(data: &mut Vec<u8>, v: Vec3) {
+    for value in v.to_array() {
+        data.write_f32::<LittleEndian>(value).unwrap();
+    }
+}
+
+/// Writes an index as u16 in BSP29 files and u32 in BSP2 ones
+fn write_index(data: &mut Vec<u8>, index: u32, format: BspFormat) -> io::Result<()> {
+    if format.is_bsp2() {
+        return data.write_u32::<LittleEndian>(index);
+    }
+    let index = u16::try_from(index).map_err(|_| does_not_fit("index", index, format))?;
+    data.write_u16::<LittleEndian>(index)
+}
+
+/// Writes a node or clipnode child, i16 in BSP29 files and i32 in BSP2 ones
+fn write_child(data: &mut Vec<u8>, child: i32, format: BspFormat) -> io::Result<()> {
+    if format.is_bsp2() {
+        return data.write_i32::<LittleEndian>(child);
+    }
+    let child = i16::try_from(child).map_err(|_| does_not_fit("child", child, format))?;
+    data.write_i16::<LittleEndian>(child)
+}
+
+/// Writes a bounding box corner, three i16 except in BSP2 files where they are floats
+fn write_bounds(data: &mut Vec<u8>, v: Vec3, format: BspFormat) -> io::Result<()> {
+    if format == BspFormat::Bsp2 {
+        write_vec3(data, v);
+        return Ok(());
+    }
+    for value in v.to_array() {
+        if !(i16::MIN as f32..=i16::MAX as f32).contains(&value.trunc()) {
+            return Err(does_not_fit("bound", value, format));
+        }
+        data.write_i16::<LittleEndian>(value as i16)?;
+    }
+    Ok(())
+}
+
+fn does_not_fit(field: &str, value: impl std::fmt::Display, format: BspFormat) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} {} does not fit a {:?} map", field, value, format),
+    )
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::image::IndexedImage;
+
+    /// A room split by one plane: an empty leaf in front, solid behind
+    pub(in crate::bsp) fn small_map() -> Vec<u8> {
+        let mut writer = BspWriter::new(29);
+        let mut worldspawn = Entity::default();
+        worldspawn
+            .fields
+            .push(("classname".to_string(), "worldspawn".to_string()));
+        writer.set_entities(&[worldspawn]);
+        writer.set_planes(&[Plane {
+            normal: Vec3::X,
+            dist: 0.0,
+            plane_type: 0,
+        }]);
+        let texture = IndexedImage::new(16, 16, (0..=255).collect()).unwrap();
+        let palette: Vec<(u8, u8, u8)> = (0..=255).map(|i| (i, i, i)).collect();
+        writer.set_miptex(&[
+            Some(MipTex::from_image("wall", &texture, &palette).unwrap()),
+            None,
+        ]);
+        writer.set_vertices(&[
+            Vertex {
+                coordinates: Vec3::new(0.0, 0.0, 0.0),
+            },
+            Vertex {
+                coordinates: Vec3::new(0.0, 64.0, 0.0),
+            },
+            Vertex {
+                coordinates: Vec3::new(0.0, 64.0, 64.0),
+            },
+        ]);
+        writer
+            .set_edges(&[
+                Edge {
+                    start_vertex: 0,
+                    end_vertex: 0,
+                },
+                Edge {
+                    start_vertex: 0,
+                    end_vertex: 1,
+                },
+                Edge {
+                    start_vertex: 1,
+                    end_vertex: 2,
+                },
+                Edge {
+                    start_vertex: 2,
+                    end_vertex: 0,
+                },
+            ])
+            .unwrap();
+        writer.set_ledges(&[1, 2, 3]);
+        writer.set_texinfo(&[TexInfo {
+            s_vector: Vec3::Y,
+            s_offset: 0.0,
+            t_vector: Vec3::NEG_Z,
+            t_offset: 0.0,
+            miptex_id: 0,
+            flags: 0,
+        }]);
+        writer
+            .set_faces(&[Face {
+                plane_id: 0,
+                side: 0,
+                ledge_id: 0,
+                ledge_num: 3,
+                texinfo_id: 0,
+                typelight: 0,
+                baselight: 255,
+                light: [255, 255],
+                lightmap: 0,
+            }])
+            .unwrap();
+        writer.set_lightmaps(vec![200; 25]);
+        writer
+            .set_nodes(&[Node {
+                plane_id: 0,
+                children: [-2, -1],
+                mins: Vec3::ZERO,
+                maxs: Vec3::splat(64.0),
+                face_id: 0,
+                face_num: 1,
+            }])
+            .unwrap();
+        let leaf = |contents, mark_surface_num| Leaf {
+            contents,
+            visofs: -1,
+            mins: Vec3::ZERO,
+            maxs: Vec3::splat(64.0),
+            mark_surface_id: 0,
+            mark_surface_num,
+            ambient_level: [0; 4],
+        };
+        writer.set_leaves(&[leaf(-2, 0), leaf(-1, 1)]).unwrap();
+        writer.set_mark_surfaces(&[0]).unwrap();
+        writer
+            .set_clipnodes(&[ClipNode {
+                plane_id: 0,
+                children: [-1, -2],
+            }])
+            .unwrap();
+        writer.set_models(&[BspModel {
+            mins: Vec3::ZERO,
+            maxs: Vec3::splat(64.0),
+            origin: Vec3::ZERO,
+            headnodes: [0, 0, 0, 0],
+            visleafs: 1,
+            face_id: 0,
+            face_num: 1,
+        }]);
+        writer.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn round_trip_preserves_bytes() {
+        let data = small_map();
+        let bsp = Bsp::load(data.clone()).unwrap();
+        assert_eq!(BspWriter::from_bsp(&bsp).to_bytes().unwrap(), data);
+
+        // Same through the typed readers
+        let header = bsp.read_header();
+        let mut writer = BspWriter::new(header.version);
+        writer.set_entities_text("{\n\"classname\" \"worldspawn\"\n}\n");
+        writer.set_planes(&bsp.read_planes(&header));
+        writer.set_miptex(&bsp.read_miptex(&header));
+        writer.set_vertices(&bsp.read_vertices(&header));
+        writer.set_edges(&bsp.read_edges(&header)).unwrap();
+        writer.set_ledges(&bsp.read_ledges(&header));
+        writer.set_texinfo(&bsp.read_texinfo(&header));
+        writer.set_faces(&bsp.read_faces(&header)).unwrap();
+        writer.set_lightmaps(bsp.read_lightmaps(&header));
+        writer.set_nodes(&bsp.read_nodes(&header)).unwrap();
+        writer.set_leaves(&bsp.read_leaves(&header)).unwrap();
+        writer
+            .set_mark_surfaces(&bsp.read_mark_surfaces(&header))
+            .unwrap();
+        writer.set_clipnodes(&bsp.read_clipnodes(&header)).unwrap();
+        writer.set_models(&bsp.read_models(&header));
+        assert_eq!(writer.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn edited_maps_still_load() {
+        let bsp = Bsp::load(small_map()).unwrap();
+        let mut writer = BspWriter::from_bsp(&bsp);
+
+        let mut entities = bsp.read_entities(&bsp.read_header()).unwrap();
+        entities[0].set("message", "Patched");
+        writer.set_entities(&entities);
+        writer.strip_lightmaps();
+
+        let edited = Bsp::load(writer.to_bytes().unwrap()).unwrap();
+        let header = edited.read_header();
+        assert_eq!(
+            edited.read_entities(&header).unwrap()[0].message(),
+            Some("Patched")
+        );
+        assert!(edited.read_lightmaps(&header).is_empty());
+        assert_eq!(edited.read_faces(&header)[0].lightmap, u32::MAX);
+    }
+
+    #[test]
+    fn oversized_indices_are_errors() {
+        let mut writer = BspWriter::new(29);
+        let edge = || Edge {
+            start_vertex: 70000,
+            end_vertex: 0,
+        };
+        let error = writer.set_edges(&[edge()]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(writer
+            .set_clipnodes(&[ClipNode {
+                plane_id: 0,
+                children: [40000, -1],
+            }])
+            .is_err());
+
+        // BSP2 has room for them
+        let mut writer = BspWriter::new(crate::bsp::BSP2_VERSION);
+        writer.set_edges(&[edge()]).unwrap();
+    }
+
+    #[test]
+    fn blue_shift_order_and_header_gap_are_kept() {
+        let bsp = Bsp::load(small_map()).unwrap();
+        let mut writer = BspWriter::from_bsp(&bsp);
+        writer.version = 30;
+        writer.set_miptex(&[]);
+        let mut data = writer.to_bytes().unwrap();
+
+        // Planes first in the header, then 8 bytes before the first lump
+        let (entities, planes) = data[4..20].split_at_mut(8);
+        entities.swap_with_slice(planes);
+        data.splice(HEADER_SIZE..HEADER_SIZE, [0xaa; 8]);
+        for entry in data[4..HEADER_SIZE].chunks_exact_mut(8) {
+            let offset = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            entry[..4].copy_from_slice(&(offset + 8).to_le_bytes());
+        }
+
+        let bsp = Bsp::load(data.clone()).unwrap();
+        assert_eq!(bsp.read_planes(&bsp.read_header()).len(), 1);
+        let mut writer = BspWriter::from_bsp(&bsp);
+        assert_eq!(writer.to_bytes().unwrap(), data);
+
+        // Still a Blue Shift map once edited, even with entities the size of whole planes
+        let text = "{\n\"message\" \"Blue Shift, edited map\"\n}\n";
+        assert_eq!(text.len() + 1, 40);
+        writer.set_entities_text(text);
+        let edited = Bsp::load(writer.to_bytes().unwrap()).unwrap();
+        let header = edited.read_header();
+        assert_eq!(edited.read_entities_text(&header).unwrap(), text);
+        assert_eq!(edited.read_planes(&header).len(), 1);
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::pak::Pak;
+
+/// Layered search path over PAK archives and loose directories
+#[derive(Default)]
+pub struct FileSystem {
+    /// Highest priority first
+    search_paths: Vec<SearchPath>,
+}
+
+enum SearchPath {
+    Pak(Pak),
+    Directory(PathBuf),
+}
+
+impl FileSystem {
+    /// Mounts a game directory ("id1", a mod...) on top of the current search path.
+    ///
+    /// Like COM_AddGameDirectory, loose files in the directory are searched after
+    /// its pak0.pak, pak1.pak... which are themselves searched highest number first.
+    /// Any *.pk3 come on top of those, the last one in alphabetical order winning.
+    pub fn add_game_directory<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Game directory {} not found", dir.display()),
+            ));
+        }
+
+        self.search_paths
+            .insert(0, SearchPath::Directory(dir.to_path_buf()));
+
+        // pak0.pak, pak1.pak... until the first missing one
+        for i in 0.. {
+            let Some(pak_path) = find_pak(dir, i)? else {
+                break;
+            };
+            let pak = Pak::open_mapped(&pak_path.to_string_lossy())?;
+            self.search_paths.insert(0, SearchPath::Pak(pak));
+        }
+
+        for pk3_path in find_pk3s(dir)? {
+            let pak = Pak::open_mapped(&pk3_path.to_string_lossy())?;
+            self.search_paths.insert(0, SearchPath::Pak(pak));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the highest priority copy of a file by path "maps/e1m1.bsp", None if no search
+    /// path has it.
+    ///
+    /// Paths must stay inside the search paths: absolute ones and ".." are rejected.
+    pub fn open(&self, path: &str) -> io::Result<Option<Cow<'_, [u8]>>> {
+        check_game_path(path)?;
+        for search_path in &self.search_paths {
+            match search_path {
+                SearchPath::Pak(pak) => {
+                    if let Some(data) = pak.find_file(path)? {
+                        return Ok(Some(Cow::Borrowed(data)));
+                    }
+                }
+                SearchPath::Directory(dir) => match fs::read(dir.join(path)) {
+                    Ok(data) => return Ok(Some(Cow::Owned(data))),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path, e))),
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Rejects paths that could leave a search directory: absolute, drive letters, ".."
+fn check_game_path(path: &str) -> io::Result<()> {
+    let escapes = path.starts_with(['/', '\\'])
+        || path.contains(':')
+        || Path::new(path).is_absolute()
+        || path.split(['/', '\\']).any(|part| part == "..");
+    if path.is_empty() || escapes {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid game path {:?}", path),
+        ));
+    }
+    Ok(())
+}
+
+/// Looks for "pak<i>.pak" in a directory, ignoring case (id1 ships "PAK0.PAK")
+fn find_pak(dir: &Path, i: u32) -> io::Result<Option<PathBuf>> {
+    let name = format!("pak{}.pak", i);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .eq_ignore_ascii_case(&name)
+        {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns every "*.pk3" in a directory, sorted by name
+fn find_pk3s(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut pk3s = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_pk3 = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pk3"));
+        if is_pk3 && path.is_file() {
+            pk3s.push(path);
+        }
+    }
+    pk3s.sort();
+    Ok(pk3s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak::PakWriter;
+
+    fn write_pak(path: &Path, files: &[(&str, &str)]) {
+        let mut writer = PakWriter::new();
+        for (name, data) in files {
+            writer.add_file(name, data.as_bytes().to_vec()).unwrap();
+        }
+        writer.save(&path.to_string_lossy()).unwrap();
+    }
+
+    fn read(filesystem: &FileSystem, path: &str) -> String {
+        let data = filesystem.open(path).unwrap().unwrap();
+        String::from_utf8(data.into_owned()).unwrap()
+    }
+
+    #[test]
+    fn search_path_priority() {
+        let root = std::env::temp_dir().join(format!("quake-fs-{}", std::process::id()));
+        let (id1, game) = (root.join("id1"), root.join("mod"));
+        fs::create_dir_all(&id1).unwrap();
+        fs::create_dir_all(&game).unwrap();
+        write_pak(
+            &id1.join("pak0.pak"),
+            &[("a.txt", "pak0"), ("b.txt", "pak0")],
+        );
+        write_pak(&id1.join("pak1.pak"), &[("b.txt", "pak1")]);
+        fs::write(id1.join("b.txt"), "id1").unwrap();
+        fs::write(id1.join("c.txt"), "id1").unwrap();
+        fs::write(game.join("a.txt"), "mod").unwrap();
+
+        let mut filesystem = FileSystem::default();
+        filesystem.add_game_directory(&id1).unwrap();
+        filesystem.add_game_directory(&game).unwrap();
+
+        // A mod loose file beats the id1 paks, a later pak beats an earlier one and
+        // beats the loose files of its own directory
+        assert_eq!(read(&filesystem, "a.txt"), "mod");
+        assert_eq!(read(&filesystem, "B.TXT"), "pak1");
+        assert_eq!(read(&filesystem, "c.txt"), "id1");
+        assert!(filesystem.open("d.txt").unwrap().is_none());
+
+        for path in ["../id1/c.txt", "/etc/passwd", "maps/../../c.txt", ""] {
+            let error = filesystem.open(path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
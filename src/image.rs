@@ -0,0 +1,335 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+/// 8-bit paletted picture, one palette index per pixel
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,                    // width * height indices, row by row
+    pub palette: Option<Vec<(u8, u8, u8)>>, // Embedded palette (Half-Life), None for the Quake one
+}
+
+/// Mip texture as stored in WAD2/WAD3 files and the BSP miptex lump
+#[derive(Debug, Clone)]
+pub struct MipTex {
+    pub name: String, // 16 bytes null terminated
+    pub width: u32,
+    pub height: u32,
+    pub mips: Vec<IndexedImage>, // full size, 1/2, 1/4 and 1/8, none if external
+}
+
+impl IndexedImage {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> io::Result<Self> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}x{} image with {} pixels", width, height, pixels.len()),
+            ));
+        }
+        Ok(IndexedImage {
+            width,
+            height,
+            pixels,
+            palette: None,
+        })
+    }
+
+    /// Reads a qpic: width and height followed by the indices ("gfx/*.lmp", WAD "B" lumps)
+    pub fn from_qpic(data: &[u8]) -> io::Result<Self> {
+        let mut cursor = io::Cursor::new(data);
+        let width = cursor.read_u32::<LittleEndian>()?;
+        let height = cursor.read_u32::<LittleEndian>()?;
+        let pixels = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|size| data.get(8..8 + size))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}x{} qpic larger than its data", width, height),
+                )
+            })?;
+        IndexedImage::new(width, height, pixels.to_vec())
+    }
+
+    /// Reads a Half-Life qpic, followed by its own palette (WAD3 "B" lumps)
+    pub fn from_qpic_with_palette(data: &[u8]) -> io::Result<Self> {
+        let mut image = IndexedImage::from_qpic(data)?;
+        image.palette = Some(read_embedded_palette(data, 8 + image.pixels.len())?);
+        Ok(image)
+    }
+
+    /// Reads a Half-Life font: a qpic with 256 glyph positions, followed by its palette (WAD3 "F" lumps)
+    pub fn from_font(data: &[u8]) -> io::Result<Self> {
+        let mut cursor = io::Cursor::new(data);
+        let width = cursor.read_u32::<LittleEndian>()?;
+        let height = cursor.read_u32::<LittleEndian>()?;
+        let start = 16 + 256 * 4; // row count, row height and glyph table
+        let pixels = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|size| data.get(start..start + size))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}x{} font larger than its data", width, height),
+                )
+            })?;
+        let mut image = IndexedImage::new(width, height, pixels.to_vec())?;
+        image.palette = Some(read_embedded_palette(data, start + pixels.len())?);
+        Ok(image)
+    }
+
+    /// Returns the palette this image should be decoded with: its own, or the given default
+    pub fn palette<'a>(&'a self, default: &'a [(u8, u8, u8)]) -> &'a [(u8, u8, u8)] {
+        self.palette.as_deref().unwrap_or(default)
+    }
+
+    /// Converts to packed RGB24, using the embedded palette if any or else one from
+    /// `palette::convert_palette`
+    pub fn to_rgb(&self, palette: &[(u8, u8, u8)]) -> Vec<u8> {
+        let palette = self.palette(palette);
+        let mut rgb = Vec::with_capacity(self.pixels.len() * 3);
+        for &index in &self.pixels {
+            let (r, g, b) = palette.get(index as usize).copied().unwrap_or((0, 0, 0));
+            rgb.extend_from_slice(&[r, g, b]);
+        }
+        rgb
+    }
+
+    /// Writes the image as an RGB PNG
+    pub fn write_png<W: Write>(&self, writer: W, palette: &[(u8, u8, u8)]) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&self.to_rgb(palette))?;
+        png_writer.finish()?;
+        Ok(())
+    }
+
+    /// Writes the image to a PNG file
+    pub fn save_png(&self, filepath: &str, palette: &[(u8, u8, u8)]) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        self.write_png(&mut writer, palette)?;
+        writer.flush()
+    }
+}
+
+impl MipTex {
+    /// Reads a Half-Life miptex, whose last mip level is followed by its own palette
+    pub fn from_bytes_with_palette(data: &[u8]) -> io::Result<Self> {
+        let mut miptex = MipTex::from_bytes(data)?;
+        if miptex.mips.is_empty() {
+            return Ok(miptex); // external, the palette is in the WAD3
+        }
+        let mut cursor = io::Cursor::new(data);
+        cursor.set_position(36); // offset of the last mip level
+        let palette_offset =
+            cursor.read_u32::<LittleEndian>()? as usize + miptex.mips[3].pixels.len();
+        let palette = read_embedded_palette(data, palette_offset)?;
+        for mip in miptex.mips.iter_mut() {
+            mip.palette = Some(palette.clone());
+        }
+        Ok(miptex)
+    }
+
+    /// Returns the embedded palette of a Half-Life texture
+    pub fn palette(&self) -> Option<&[(u8, u8, u8)]> {
+        self.mips.first()?.palette.as_deref()
+    }
+
+    /// Reads a miptex: 16 byte name, size, then four offsets to the mip levels
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut cursor = io::Cursor::new(data);
+
+        let mut name_buf = [0u8; 16];
+        cursor.read_exact(&mut name_buf)?;
+        let name_len = name_buf.iter().position(|&b| b == 0).unwrap_or(16);
+        let name = String::from_utf8_lossy(&name_buf[..name_len]).to_string();
+
+        let width = cursor.read_u32::<LittleEndian>()?;
+        let height = cursor.read_u32::<LittleEndian>()?;
+        let mut offsets = [0u32; 4];
+        for offset in offsets.iter_mut() {
+            *offset = cursor.read_u32::<LittleEndian>()?;
+        }
+
+        // Textures kept in an external WAD only have their header in the BSP
+        let mut mips = Vec::new();
+        if offsets.iter().all(|&offset| offset == 0) {
+            return Ok(MipTex {
+                name,
+                width,
+                height,
+                mips,
+            });
+        }
+
+        for (level, &offset) in offsets.iter().enumerate() {
+            let mip_width = width >> level;
+            let mip_height = height >> level;
+            let start = offset as usize;
+            let end = start + mip_width as usize * mip_height as usize;
+            let pixels = data.get(start..end).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Mip level {} of {} lies outside the texture", level, name),
+                )
+            })?;
+            mips.push(IndexedImage::new(mip_width, mip_height, pixels.to_vec())?);
+        }
+
+        Ok(MipTex {
+            name,
+            width,
+            height,
+            mips,
+        })
+    }
+
+    /// Builds a miptex from its full size image, generating the three smaller mip levels.
+    ///
+    /// Like qlumpy, each smaller pixel is the average colour of the block it covers, mapped
+    /// back to the nearest palette entry. Sizes must be multiples of 16.
+    pub fn from_image(
+        name: &str,
+        image: &IndexedImage,
+        palette: &[(u8, u8, u8)],
+    ) -> io::Result<Self> {
+        if name.len() > 15 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Texture name {} longer than 15 characters", name),
+            ));
+        }
+        if image.width == 0
+            || image.height == 0
+            || !image.width.is_multiple_of(16)
+            || !image.height.is_multiple_of(16)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Texture {} is {}x{}, sizes must be multiples of 16",
+                    name, image.width, image.height
+                ),
+            ));
+        }
+
+        // Quake textures use the global palette
+        let mut base = image.clone();
+        base.palette = None;
+        let mut mips = vec![base];
+        for level in 1..4 {
+            let scale = 1usize << level;
+            let mip_width = image.width as usize / scale;
+            let mip_height = image.height as usize / scale;
+            let mut pixels = Vec::with_capacity(mip_width * mip_height);
+
+            for y in 0..mip_height {
+                for x in 0..mip_width {
+                    let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+                    for by in 0..scale {
+                        for bx in 0..scale {
+                            let index = image.pixels
+                                [(y * scale + by) * image.width as usize + x * scale + bx];
+                            let color = palette.get(index as usize).copied().unwrap_or((0, 0, 0));
+                            r += color.0 as u32;
+                            g += color.1 as u32;
+                            b += color.2 as u32;
+                        }
+                    }
+                    let count = (scale * scale) as u32;
+                    pixels.push(nearest_color(palette, (r / count, g / count, b / count)));
+                }
+            }
+            mips.push(IndexedImage::new(
+                mip_width as u32,
+                mip_height as u32,
+                pixels,
+            )?);
+        }
+
+        Ok(MipTex {
+            name: name.to_string(),
+            width: image.width,
+            height: image.height,
+            mips,
+        })
+    }
+
+    /// Serializes the miptex header followed by the four mip levels, and the palette of
+    /// Half-Life textures
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        let mut name_buf = [0u8; 16];
+        let name_len = self.name.len().min(15);
+        name_buf[..name_len].copy_from_slice(&self.name.as_bytes()[..name_len]);
+        data.extend_from_slice(&name_buf);
+        data.write_u32::<LittleEndian>(self.width).unwrap();
+        data.write_u32::<LittleEndian>(self.height).unwrap();
+
+        let mut offset = 40; // name, size and the offsets themselves
+        for mip in &self.mips {
+            data.write_u32::<LittleEndian>(offset).unwrap();
+            offset += mip.pixels.len() as u32;
+        }
+        if self.mips.is_empty() {
+            data.extend_from_slice(&[0u8; 16]); // external texture
+        }
+        for mip in &self.mips {
+            data.extend_from_slice(&mip.pixels);
+        }
+
+        if let Some(palette) = self.palette() {
+            data.write_u16::<LittleEndian>(palette.len() as u16)
+                .unwrap();
+            for &(r, g, b) in palette {
+                data.extend_from_slice(&[r, g, b]);
+            }
+            data.extend_from_slice(&[0, 0]); // padding
+        }
+
+        data
+    }
+}
+
+/// Returns the palette index closest to a colour, index 255 is left for transparency
+fn nearest_color(palette: &[(u8, u8, u8)], color: (u32, u32, u32)) -> u8 {
+    let mut best = 0;
+    let mut best_distance = u32::MAX;
+    for (index, &(r, g, b)) in palette.iter().enumerate().take(255) {
+        let dr = r as i32 - color.0 as i32;
+        let dg = g as i32 - color.1 as i32;
+        let db = b as i32 - color.2 as i32;
+        let distance = (dr * dr + dg * dg + db * db) as u32;
+        if distance < best_distance {
+            best = index as u8;
+            best_distance = distance;
+        }
+    }
+    best
+}
+
+/// Reads a Half-Life palette: a colour count then that many RGB triplets
+fn read_embedded_palette(data: &[u8], offset: usize) -> io::Result<Vec<(u8, u8, u8)>> {
+    let mut cursor = io::Cursor::new(data);
+    cursor.set_position(offset as u64);
+    let count = cursor.read_u16::<LittleEndian>()? as usize;
+    if count > 256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Embedded palette with {} colours", count),
+        ));
+    }
+    let mut palette = Vec::with_capacity(count);
+    for _ in 0..count {
+        palette.push((cursor.read_u8()?, cursor.read_u8()?, cursor.read_u8()?));
+    }
+    Ok(palette)
+}
@@ -0,0 +1,7 @@
+pub mod bsp;
+pub mod fs;
+pub mod image;
+pub mod models;
+pub mod pak;
+pub mod palette;
+pub mod wad;
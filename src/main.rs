@@ -1,35 +1,45 @@
 use std::time::{Duration, Instant};
 
 use crate::config::*;
-use crate::models::{Model, ModelHeader};
 use crate::render::*;
 
-use bsp::{Edge, Vertex};
 use glam::Vec3;
 use music::handle_music;
-use pak::Pak;
+use quake::bsp::{self, Edge, Vertex};
+use quake::fs::FileSystem;
+use quake::models::{self, Model, ModelHeader};
+use quake::{palette, wad};
 use sdl2::{event::Event, keyboard::Keycode};
 
-mod bsp;
 mod config;
-mod models;
 mod music;
-mod pak;
-mod palette;
 mod render;
-mod wad;
 
 fn main() -> Result<(), String> {
-    // Load the .PAK file
-    let pak0 = Pak::new("id1/PAK0.PAK").expect("Failed to open PAK0 file");
-    let pak1 = Pak::new("id1/PAK1.PAK").expect("Failed to open PAK1 file");
+    // Mount id1 and an optional mod directory ("-game <dir>") on top of it
+    let mut filesystem = FileSystem::default();
+    filesystem
+        .add_game_directory("id1")
+        .expect("Failed to mount id1");
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(game) = argument(&args, "-game") {
+        filesystem
+            .add_game_directory(game)
+            .expect("Failed to mount game directory");
+    }
 
     // Load the Quake palette
-    let palette_data = pak0.find_file("gfx/palette.lmp").unwrap();
+    let palette_data = filesystem
+        .open("gfx/palette.lmp")
+        .map_err(|e| e.to_string())?
+        .expect("Palette not found");
     let converted_palette = palette::convert_palette(&palette_data);
 
     // Load the player.mdl file
-    let mdl_data = pak0.find_file("progs/player.mdl").expect("Model not found");
+    let mdl_data = filesystem
+        .open("progs/player.mdl")
+        .map_err(|e| e.to_string())?
+        .expect("Model not found");
     let mut reader = std::io::Cursor::new(&mdl_data);
 
     // Parse the model header
@@ -46,16 +56,53 @@ fn main() -> Result<(), String> {
         model_triangles: vec![], // Placeholder: Load or parse as needed
     };
 
-    let wad = wad::Wad::new(pak0.find_file("gfx.wad").unwrap());
-    let bsp = bsp::Bsp::new(pak0.find_file("maps/start.bsp").unwrap());
+    let wad = wad::Wad::new(
+        filesystem
+            .open("gfx.wad")
+            .map_err(|e| e.to_string())?
+            .expect("gfx.wad not found")
+            .into_owned(),
+    );
+    // "+map e1m1" like the original, start.bsp otherwise
+    let map = argument(&args, "+map").map_or("start", String::as_str);
+    let bsp = bsp::Bsp::load(
+        filesystem
+            .open(&format!("maps/{}.bsp", map))
+            .map_err(|e| e.to_string())?
+            .expect("Map not found")
+            .into_owned(),
+    )
+    .map_err(|error| format!("Failed to load maps/{}.bsp: {}", map, error))?;
 
     let bsp_header = bsp.read_header();
 
     let vertices = bsp.read_vertices(&bsp_header);
     let edges = bsp.read_edges(&bsp_header);
-    //let entities = bsp.read_entities(&bsp_header);
+    // A maps/<name>.ent file replaces the entity lump, like in most source ports
+    let entities = match filesystem
+        .open(&format!("maps/{}.ent", map))
+        .map_err(|e| e.to_string())?
+    {
+        Some(text) => bsp::parse_entities(&String::from_utf8_lossy(&text)),
+        None => bsp.read_entities(&bsp_header),
+    }
+    .expect("Failed to parse entities");
     let faces = bsp.read_faces(&bsp_header);
-    let planes = bsp.read_planes(&bsp_header);
+    let tree = bsp.read_tree(&bsp_header);
+    let bsp_models = bsp.read_models(&bsp_header);
+
+    // Doors, plats, buttons... drawn with the inline model of their entity. Triggers are
+    // invisible volumes, InitTrigger clears their model.
+    let brush_entities: Vec<(Vec3, Vec<u32>)> = entities
+        .iter()
+        .filter(|entity| !entity.classname().starts_with("trigger_"))
+        .filter_map(|entity| {
+            let model = bsp_models.get(entity.brush_model()?)?;
+            let origin = entity.origin().unwrap_or(Vec3::ZERO);
+            Some((origin, model.faces().collect::<Vec<u32>>()))
+        })
+        .filter(|(_, faces)| !faces.is_empty())
+        .collect();
     let ledges = bsp.read_ledges(&bsp_header);
 
     println!("{:?}", bsp_header);
@@ -79,18 +126,20 @@ fn main() -> Result<(), String> {
         .build()
         .expect("Could not create canvas");
 
+    let (position, yaw, pitch) = find_spawn_point(&entities, &vertices);
     let mut camera = Camera {
-        position: Vec3::new(538.0, 284.0, 28.0), // hardcoded for start.bsp, will be dependent on level later
-        forward: Vec3::new(0.0, 1.0, 0.0),       // Looking toward Y
-        up: Vec3::new(0.0, 0.0, 1.0),            // Z is up
-        right: Vec3::new(1.0, 0.0, 0.0),         // X is right
-        yaw: 0.0,
-        pitch: 0.0,
+        position,
+        forward: Vec3::new(0.0, 1.0, 0.0), // Looking toward Y
+        up: Vec3::new(0.0, 0.0, 1.0),      // Z is up
+        right: Vec3::new(1.0, 0.0, 0.0),   // X is right
+        yaw,
+        pitch,
         fov: 125.0,
         aspect_ratio: 320.0 / 200.0,
         near: 0.1,
         far: 1200.0,
     };
+    camera.update_direction();
 
     let mut event_pump = sdl_context.event_pump()?;
     let mut last_frame_time = Instant::now();
@@ -124,6 +173,16 @@ fn main() -> Result<(), String> {
             }
         }
 
+        // The world (model 0) is culled by the PVS of the camera leaf
+        let camera_leaf = tree.point_in_leaf(camera.position);
+        let world_faces = tree.visible_faces(camera_leaf);
+        let mut models: Vec<(Vec3, &[u32])> = vec![(Vec3::ZERO, &world_faces)];
+        models.extend(
+            brush_entities
+                .iter()
+                .map(|(origin, faces)| (*origin, faces.as_slice())),
+        );
+
         render(
             &mut canvas,
             &converted_palette,
@@ -132,7 +191,7 @@ fn main() -> Result<(), String> {
             &vertices,
             &edges,
             &faces,
-            &planes,
+            &models,
             &ledges,
         );
 
@@ -142,3 +201,10 @@ fn main() -> Result<(), String> {
 
     Ok(())
 }
+
+/// Returns the value following a command line option ("-game", "+map"...)
+fn argument<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+}
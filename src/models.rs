@@ -1,8 +1,8 @@
-use std::io::Cursor;
-
 use byteorder::{LittleEndian, ReadBytesExt};
 use glam::Vec3;
 
+use crate::image::IndexedImage;
+
 pub struct ModelHeader {
     pub ident: u32,   // "IDPO"
     pub version: u32, // 6
@@ -29,6 +29,17 @@ pub struct Model {
     // pub model_frames
 }
 
+impl Model {
+    /// Returns the skin as an indexed image
+    pub fn skin_image(&self) -> Result<IndexedImage, std::io::Error> {
+        IndexedImage::new(
+            self.header.skinwidth,
+            self.header.skinheight,
+            self.skin_data.clone(),
+        )
+    }
+}
+
 impl ModelHeader {
     pub fn from_reader<R: std::io::Read>(reader: &mut R) -> Result<Self, std::io::Error> {
         let ident = reader.read_u32::<LittleEndian>()?;
@@ -88,12 +99,40 @@ impl ModelHeader {
     }
 }
 
+/// Reads every skin and returns the first one (the first frame of a skin group).
+///
+/// Each skin starts with its type: 0 for a single picture, 1 for a group of pictures
+/// played at the intervals that follow their count.
 pub fn parse_skins<R: std::io::Read>(
     reader: &mut R,
     header: &ModelHeader,
 ) -> Result<Vec<u8>, std::io::Error> {
     let skin_size = (header.skinwidth * header.skinheight) as usize;
-    let mut skins = vec![0; skin_size];
-    reader.read_exact(&mut skins)?;
-    Ok(skins)
+    let mut first = None;
+
+    for _ in 0..header.numskins {
+        let frames = match reader.read_u32::<LittleEndian>()? {
+            0 => 1,
+            1 => {
+                let count = reader.read_u32::<LittleEndian>()?;
+                for _ in 0..count {
+                    reader.read_f32::<LittleEndian>()?; // interval
+                }
+                count
+            }
+            group => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid MDL skin type {}", group),
+                ))
+            }
+        };
+        for _ in 0..frames {
+            let mut skin = vec![0; skin_size];
+            reader.read_exact(&mut skin)?;
+            first.get_or_insert(skin);
+        }
+    }
+
+    first.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "MDL has no skin"))
 }
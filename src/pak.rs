@@ -1,11 +1,31 @@
-use std::{fs::File, io, io::Read};
+use std::{collections::HashMap, fs::File, io, io::Read, sync::OnceLock};
+
+pub use self::writer::PakWriter;
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use memmap2::Mmap;
+mod pk3;
+mod writer;
 
 #[derive(Debug)]
 pub struct Pak {
     /// Raw pak data
-    pub data: Vec<u8>,
+    data: PakData,
+    /// Directory entries, in archive order
+    pub files: Vec<PakFile>,
+    /// Normalised name -> index in `files`
+    index: HashMap<String, usize>,
+    /// Decompressed .pk3 entries, filled on first lookup
+    inflated: Vec<OnceLock<Vec<u8>>>,
+}
+
+/// Where the archive bytes live, the rest of the engine does not care
+#[derive(Debug)]
+enum PakData {
+    /// Whole file read up front
+    Memory(Vec<u8>),
+    /// File mapped into memory, entries are paged in by the OS when touched
+    Mapped(Mmap),
 }
 
 pub struct PakHeader {
@@ -16,84 +36,248 @@ pub struct PakHeader {
 
 #[derive(Debug)]
 pub struct PakFile {
-    pub name: String, // 56 bytes null terminated ex : "maps/e1m1.bsp"
+    pub name: String,       // 56 bytes null terminated ex : "maps/e1m1.bsp"
+    pub raw_name: [u8; 56], // name bytes as stored, may hold junk after the null
     pub file_offset: u32,
-    pub file_size: u32,
+    pub file_size: u32,           // Size in the archive
+    pub compression: Compression, // Always stored in PACK files
+    pub size: u32,                // Size once decompressed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Stored,
+    Deflated,
 }
 
 impl Pak {
-    /// Creates a PAK from a file
+    /// Creates a PAK from a file, either a PACK archive or a zip based .pk3
     pub fn new(filepath: &str) -> io::Result<Self> {
         let mut pak_file = File::open(filepath)?;
         let mut data: Vec<u8> = Vec::new();
         pak_file.read_to_end(&mut data)?;
-        Ok(Pak { data })
+        Pak::from_bytes(data)
     }
 
-    /// Returns a PAK Header
-    pub fn read_header(&self) -> io::Result<PakHeader> {
-        let mut cursor = io::Cursor::new(&self.data);
+    /// Creates a PAK from a memory-mapped file, only the directory is read eagerly
+    pub fn open_mapped(filepath: &str) -> io::Result<Self> {
+        let pak_file = File::open(filepath)?;
+        // SAFETY: pak files are treated as read-only game data, nothing is expected
+        // to truncate or rewrite them while the engine is running
+        let mmap = unsafe { Mmap::map(&pak_file)? };
+        Pak::index(PakData::Mapped(mmap))
+    }
+
+    /// Creates a PAK from raw data, indexing its directory
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Self> {
+        Pak::index(PakData::Memory(data))
+    }
 
-        // Read the first 4 bytes as a string
-        let mut id_buf = [0u8; 4];
-        cursor.read_exact(&mut id_buf)?;
-        let id = String::from_utf8_lossy(&id_buf).to_string();
+    fn index(data: PakData) -> io::Result<Self> {
+        let mut pak = Pak {
+            data,
+            files: Vec::new(),
+            index: HashMap::new(),
+            inflated: Vec::new(),
+        };
+        pak.files = if pk3::is_pk3(pak.data()) {
+            pk3::read_directory(pak.data())?
+        } else {
+            pak.read_directory()?
+        };
+        pak.inflated = pak.files.iter().map(|_| OnceLock::new()).collect();
 
-        if id != "PACK" {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Unsupported PAK file",
-            ));
+        for (i, file) in pak.files.iter().enumerate() {
+            let end = file.file_offset as u64 + file.file_size as u64;
+            if end > pak.data().len() as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("PAK entry {} lies outside the file", file.name),
+                ));
+            }
+            // First entry wins, like the linear search in Quake
+            pak.index.entry(normalize_path(&file.name)).or_insert(i);
         }
 
-        // Read directory offset and size
-        let dir_offset = cursor.read_u32::<LittleEndian>()?;
-        let dir_size = cursor.read_u32::<LittleEndian>()?;
+        Ok(pak)
+    }
+
+    /// Returns the raw archive bytes, whatever the backing
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            PakData::Memory(data) => data,
+            PakData::Mapped(mmap) => mmap,
+        }
+    }
 
-        Ok(PakHeader {
-            id,
-            dir_offset,
-            dir_size,
-        })
+    /// Returns a PAK Header
+    pub fn read_header(&self) -> io::Result<PakHeader> {
+        read_header(self.data())
     }
 
     pub fn read_directory(&self) -> io::Result<Vec<PakFile>> {
-        let header = self.read_header()?;
-
-        let file_number = header.dir_size / 64;
-
-        let mut pakfiles: Vec<PakFile> = Vec::new();
-
-        let mut cursor = io::Cursor::new(&self.data);
-        cursor.set_position(header.dir_offset.into());
-        for _ in 0..file_number {
-            let mut file_buf = [0u8; 56]; // 56 bytes
-            cursor.read_exact(&mut file_buf)?;
-            let name = String::from_utf8_lossy(&file_buf)
-                .trim_matches(char::from(0))
-                .to_string();
-            let file_offset = cursor.read_u32::<LittleEndian>()?;
-            let file_size = cursor.read_u32::<LittleEndian>()?;
-            pakfiles.push(PakFile {
-                name,
-                file_offset,
-                file_size,
-            });
+        read_directory(self.data())
+    }
+
+    /// Returns file by path "maps/e1m1.bsp", ignoring case and slash direction, None if the
+    /// archive does not have it. Fails if a .pk3 entry does not decompress.
+    pub fn find_file(&self, path: &str) -> io::Result<Option<&[u8]>> {
+        match self.index.get(&normalize_path(path)) {
+            Some(&i) => self.file_data(i).map(Some),
+            None => Ok(None),
         }
+    }
+
+    /// Returns the contents of the entry at `files[file_index]`, decompressing it if needed
+    pub fn file_data(&self, file_index: usize) -> io::Result<&[u8]> {
+        let file = &self.files[file_index];
+        let start = file.file_offset as usize;
+        let stored = &self.data()[start..start + file.file_size as usize];
+
+        match file.compression {
+            Compression::Stored => Ok(stored),
+            Compression::Deflated => {
+                let cell = &self.inflated[file_index];
+                if cell.get().is_none() {
+                    let _ = cell.set(pk3::inflate(stored, file.size)?);
+                }
+                Ok(cell.get().unwrap())
+            }
+        }
+    }
+}
+
+/// Lowercases a path and turns backslashes into "/", dropping empty and "." components
+pub fn normalize_path(path: &str) -> String {
+    path.to_ascii_lowercase()
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
 
-        Ok(pakfiles)
+/// Returns the header of raw PAK data
+pub fn read_header(data: &[u8]) -> io::Result<PakHeader> {
+    let mut cursor = io::Cursor::new(data);
+
+    // Read the first 4 bytes as a string
+    let mut id_buf = [0u8; 4];
+    cursor.read_exact(&mut id_buf)?;
+    let id = String::from_utf8_lossy(&id_buf).to_string();
+
+    if id != "PACK" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unsupported PAK file",
+        ));
+    }
+
+    // Read directory offset and size
+    let dir_offset = cursor.read_u32::<LittleEndian>()?;
+    let dir_size = cursor.read_u32::<LittleEndian>()?;
+
+    Ok(PakHeader {
+        id,
+        dir_offset,
+        dir_size,
+    })
+}
+
+/// Returns the directory entries of raw PAK data, without checking them
+pub fn read_directory(data: &[u8]) -> io::Result<Vec<PakFile>> {
+    let header = read_header(data)?;
+
+    let file_number = header.dir_size / 64;
+
+    let mut pakfiles: Vec<PakFile> = Vec::new();
+
+    let mut cursor = io::Cursor::new(data);
+    cursor.set_position(header.dir_offset.into());
+    for _ in 0..file_number {
+        let mut file_buf = [0u8; 56]; // 56 bytes
+        cursor.read_exact(&mut file_buf)?;
+        // Anything after the first null is leftover garbage from the packing tool
+        let name_len = file_buf.iter().position(|&b| b == 0).unwrap_or(56);
+        let name = String::from_utf8_lossy(&file_buf[..name_len]).to_string();
+        let file_offset = cursor.read_u32::<LittleEndian>()?;
+        let file_size = cursor.read_u32::<LittleEndian>()?;
+        pakfiles.push(PakFile {
+            name,
+            raw_name: file_buf,
+            file_offset,
+            file_size,
+            compression: Compression::Stored,
+            size: file_size,
+        });
+    }
+
+    Ok(pakfiles)
+}
+
+/// Checks raw PAK data, returning one message per problem found
+pub fn verify(data: &[u8]) -> io::Result<Vec<String>> {
+    if pk3::is_pk3(data) {
+        return verify_pk3(data);
+    }
+
+    let header = read_header(data)?;
+    let mut problems = Vec::new();
+
+    if header.dir_size % 64 != 0 {
+        problems.push(format!(
+            "Directory size {} is not a multiple of 64",
+            header.dir_size
+        ));
+    }
+    if header.dir_offset as u64 + header.dir_size as u64 > data.len() as u64 {
+        problems.push(format!(
+            "Directory at {} ({} bytes) lies outside the file",
+            header.dir_offset, header.dir_size
+        ));
+        return Ok(problems);
     }
 
-    /// Returns file by path "maps/e1m1.bsp"
-    pub fn find_file(&self, path: &str) -> Option<Vec<u8>> {
-        let directory = self.read_directory().unwrap();
-        for file in directory {
-            if file.name == path {
-                let start = file.file_offset as usize;
-                let end = start + file.file_size as usize;
-                return Some(self.data[start..end].to_vec());
+    for (i, file) in read_directory(data)?.iter().enumerate() {
+        if !file.raw_name.contains(&0) {
+            problems.push(format!("Entry {} name is not null terminated", i));
+        }
+        if file.name.is_empty() {
+            problems.push(format!("Entry {} has an empty name", i));
+        }
+        if file.file_offset as u64 + file.file_size as u64 > data.len() as u64 {
+            problems.push(format!(
+                "Entry {} {} at {} ({} bytes) lies outside the file",
+                i, file.name, file.file_offset, file.file_size
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Checks that every .pk3 entry lies inside the file and decompresses
+fn verify_pk3(data: &[u8]) -> io::Result<Vec<String>> {
+    let mut problems = Vec::new();
+
+    for (i, file) in pk3::read_directory(data)?.iter().enumerate() {
+        let start = file.file_offset as usize;
+        let end = start as u64 + file.file_size as u64;
+        if end > data.len() as u64 {
+            problems.push(format!(
+                "Entry {} {} at {} ({} bytes) lies outside the file",
+                i, file.name, file.file_offset, file.file_size
+            ));
+            continue;
+        }
+        if file.compression == Compression::Deflated {
+            if let Err(e) = pk3::inflate(&data[start..end as usize], file.size) {
+                problems.push(format!(
+                    "Entry {} {} does not decompress: {}",
+                    i, file.name, e
+                ));
             }
         }
-        None
     }
+
+    Ok(problems)
 }
@@ -0,0 +1,232 @@
+use std::io::{self, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::DeflateDecoder;
+
+use super::{Compression, PakFile};
+
+const LOCAL_HEADER: u32 = 0x04034b50; // "PK\3\4"
+const CENTRAL_HEADER: u32 = 0x02014b50; // "PK\1\2"
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50; // "PK\5\6"
+
+/// Most memory reserved up front for a decompressed entry, the size comes from the file
+const MAX_PREALLOCATION: usize = 16 << 20;
+
+/// Returns true if the data looks like a zip archive (.pk3)
+pub fn is_pk3(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06")
+}
+
+/// Returns the entries of a zip archive, reading its central directory
+pub fn read_directory(data: &[u8]) -> io::Result<Vec<PakFile>> {
+    let eocd = find_end_of_central_directory(data)?;
+    let mut cursor = io::Cursor::new(data);
+    cursor.set_position(eocd as u64 + 10);
+    let file_number = cursor.read_u16::<LittleEndian>()?;
+    let _dir_size = cursor.read_u32::<LittleEndian>()?;
+    let dir_offset = cursor.read_u32::<LittleEndian>()?;
+
+    let mut pakfiles: Vec<PakFile> = Vec::new();
+
+    cursor.set_position(dir_offset.into());
+    for _ in 0..file_number {
+        if cursor.read_u32::<LittleEndian>()? != CENTRAL_HEADER {
+            return Err(invalid("Bad PK3 central directory entry"));
+        }
+        cursor.set_position(cursor.position() + 4); // made by, version needed
+        let flags = cursor.read_u16::<LittleEndian>()?;
+        let method = cursor.read_u16::<LittleEndian>()?;
+        cursor.set_position(cursor.position() + 8); // time, date, crc
+        let compressed_size = cursor.read_u32::<LittleEndian>()?;
+        let size = cursor.read_u32::<LittleEndian>()?;
+        let name_len = cursor.read_u16::<LittleEndian>()?;
+        let extra_len = cursor.read_u16::<LittleEndian>()?;
+        let comment_len = cursor.read_u16::<LittleEndian>()?;
+        cursor.set_position(cursor.position() + 8); // disk, attributes
+        let local_offset = cursor.read_u32::<LittleEndian>()?;
+
+        let mut name_buf = vec![0u8; name_len as usize];
+        cursor.read_exact(&mut name_buf)?;
+        let name = String::from_utf8_lossy(&name_buf).to_string();
+        cursor.set_position(cursor.position() + extra_len as u64 + comment_len as u64);
+
+        // Directories have no data
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            return Err(invalid(&format!("PK3 entry {} is encrypted", name)));
+        }
+        if compressed_size == u32::MAX || size == u32::MAX {
+            return Err(invalid(&format!("PK3 entry {} needs zip64", name)));
+        }
+        let compression = match method {
+            0 => Compression::Stored,
+            8 => Compression::Deflated,
+            _ => {
+                return Err(invalid(&format!(
+                    "PK3 entry {} uses unsupported compression {}",
+                    name, method
+                )))
+            }
+        };
+
+        // Kept PACK compatible when the name fits, so entries can be repacked
+        let mut raw_name = [0u8; 56];
+        if name_buf.len() < 56 {
+            raw_name[..name_buf.len()].copy_from_slice(&name_buf);
+        }
+
+        pakfiles.push(PakFile {
+            raw_name,
+            file_offset: data_offset(data, local_offset)?,
+            file_size: compressed_size,
+            compression,
+            size,
+            name,
+        });
+    }
+
+    Ok(pakfiles)
+}
+
+/// Decompresses a deflated entry, failing if it does not give exactly `size` bytes
+pub fn inflate(compressed: &[u8], size: u32) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity((size as usize).min(MAX_PREALLOCATION));
+    // One byte more than declared is enough to tell the entry is too large
+    DeflateDecoder::new(compressed)
+        .take(size as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() != size as usize {
+        return Err(invalid("PK3 entry size mismatch"));
+    }
+    Ok(data)
+}
+
+/// The end of central directory record sits in the last 22 bytes plus the archive comment
+fn find_end_of_central_directory(data: &[u8]) -> io::Result<usize> {
+    if data.len() < 22 {
+        return Err(invalid("PK3 file too small"));
+    }
+    let lowest = data.len().saturating_sub(22 + u16::MAX as usize);
+    (lowest..=data.len() - 22)
+        .rev()
+        .find(|&i| data[i..i + 4] == END_OF_CENTRAL_DIRECTORY.to_le_bytes())
+        .ok_or_else(|| invalid("PK3 end of central directory not found"))
+}
+
+/// Entry data starts after the local header, whose name and extra field may differ from the central one
+fn data_offset(data: &[u8], local_offset: u32) -> io::Result<u32> {
+    let mut cursor = io::Cursor::new(data);
+    cursor.set_position(local_offset.into());
+    if cursor.read_u32::<LittleEndian>()? != LOCAL_HEADER {
+        return Err(invalid("Bad PK3 local header"));
+    }
+    cursor.set_position(local_offset as u64 + 26);
+    let name_len = cursor.read_u16::<LittleEndian>()?;
+    let extra_len = cursor.read_u16::<LittleEndian>()?;
+    let offset = local_offset as u64 + 30 + name_len as u64 + extra_len as u64;
+    u32::try_from(offset).map_err(|_| invalid("PK3 entry lies outside the file"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use byteorder::WriteBytesExt;
+    use flate2::{write::DeflateEncoder, Compression as Level};
+
+    use super::*;
+    use crate::pak::Pak;
+
+    /// Builds a zip archive, each entry with its declared uncompressed size
+    fn zip(entries: &[(&str, &[u8], bool, u32)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central = Vec::new();
+        for &(name, contents, deflate, size) in entries {
+            let stored = if deflate {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Level::default());
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                contents.to_vec()
+            };
+            let method = if deflate { 8 } else { 0 };
+            let local_offset = data.len() as u32;
+
+            data.write_u32::<LittleEndian>(LOCAL_HEADER).unwrap();
+            for value in [20u16, 0, method, 0, 0] {
+                data.write_u16::<LittleEndian>(value).unwrap(); // version, flags, method, time, date
+            }
+            for value in [0u32, stored.len() as u32, size] {
+                data.write_u32::<LittleEndian>(value).unwrap(); // crc, sizes
+            }
+            data.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+            data.write_u16::<LittleEndian>(0).unwrap();
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&stored);
+
+            central.write_u32::<LittleEndian>(CENTRAL_HEADER).unwrap();
+            for value in [20u16, 20, 0, method, 0, 0] {
+                central.write_u16::<LittleEndian>(value).unwrap();
+            }
+            for value in [0u32, stored.len() as u32, size] {
+                central.write_u32::<LittleEndian>(value).unwrap();
+            }
+            for value in [name.len() as u16, 0, 0, 0, 0] {
+                central.write_u16::<LittleEndian>(value).unwrap(); // name, extra, comment, disk, attributes
+            }
+            central.write_u32::<LittleEndian>(0).unwrap(); // external attributes
+            central.write_u32::<LittleEndian>(local_offset).unwrap();
+            central.extend_from_slice(name.as_bytes());
+        }
+
+        let dir_offset = data.len() as u32;
+        data.extend_from_slice(&central);
+        data.write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY)
+            .unwrap();
+        for value in [0u16, 0, entries.len() as u16, entries.len() as u16] {
+            data.write_u16::<LittleEndian>(value).unwrap();
+        }
+        data.write_u32::<LittleEndian>(central.len() as u32)
+            .unwrap();
+        data.write_u32::<LittleEndian>(dir_offset).unwrap();
+        data.write_u16::<LittleEndian>(0).unwrap(); // comment
+        data
+    }
+
+    #[test]
+    fn stored_and_deflated_entries() {
+        let text = b"quake quake quake quake quake";
+        let pak = Pak::from_bytes(zip(&[
+            ("maps/", b"", false, 0),
+            ("maps/e1m1.bsp", b"BSP", false, 3),
+            ("gfx/README.txt", text, true, text.len() as u32),
+        ]))
+        .unwrap();
+
+        // Directories are skipped
+        assert_eq!(pak.files.len(), 2);
+        assert_eq!(pak.files[1].compression, Compression::Deflated);
+        assert_eq!(pak.find_file("maps/E1M1.bsp").unwrap(), Some(&b"BSP"[..]));
+        assert_eq!(pak.find_file("gfx\\readme.txt").unwrap(), Some(&text[..]));
+        assert_eq!(pak.find_file("maps/e1m2.bsp").unwrap(), None);
+    }
+
+    #[test]
+    fn wrong_sizes_are_errors() {
+        let contents = vec![0u8; 4096];
+        let pak = Pak::from_bytes(zip(&[
+            ("bomb.bin", &contents, true, 16),
+            ("short.bin", &contents, true, 8192),
+        ]))
+        .unwrap();
+
+        assert!(pak.find_file("bomb.bin").is_err());
+        assert!(pak.find_file("short.bin").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::{normalize_path, Pak};
+
+/// Builds a "PACK" archive from named byte buffers
+#[derive(Default)]
+pub struct PakWriter {
+    entries: Vec<PakEntry>,
+}
+
+struct PakEntry {
+    name: [u8; 56], // null terminated, written as is
+    data: Vec<u8>,
+}
+
+impl PakWriter {
+    pub fn new() -> Self {
+        PakWriter {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Copies every entry of an existing PAK, keeping directory order and name bytes.
+    ///
+    /// Writing it back gives the same bytes as long as the source was laid out the usual
+    /// way: header, entry data in directory order, directory last. Entries of a .pk3 are
+    /// decompressed, and fail if their name does not fit in a PACK directory entry.
+    pub fn from_pak(pak: &Pak) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for (i, file) in pak.files.iter().enumerate() {
+            if file.name.len() > 55 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("PAK entry name {} is too long", file.name),
+                ));
+            }
+            entries.push(PakEntry {
+                name: file.raw_name,
+                data: pak.file_data(i)?.to_vec(),
+            });
+        }
+        Ok(PakWriter { entries })
+    }
+
+    /// Adds a file by path "maps/e1m1.bsp", names must fit in 55 bytes
+    pub fn add_file(&mut self, name: &str, data: Vec<u8>) -> io::Result<()> {
+        if name.is_empty() || name.len() > 55 || name.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid PAK entry name {:?}", name),
+            ));
+        }
+
+        let normalized = normalize_path(name);
+        if self
+            .entries
+            .iter()
+            .any(|entry| normalize_path(&entry.name()) == normalized)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Duplicate PAK entry {}", name),
+            ));
+        }
+
+        let mut name_buf = [0u8; 56];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        self.entries.push(PakEntry {
+            name: name_buf,
+            data,
+        });
+        Ok(())
+    }
+
+    /// Adds every file below a directory, named by their path relative to it
+    pub fn add_directory<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        self.add_directory_recursive(dir.as_ref(), "")
+    }
+
+    fn add_directory_recursive(&mut self, dir: &Path, prefix: &str) -> io::Result<()> {
+        // Sorted so the same tree always gives the same archive
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.add_directory_recursive(&entry.path(), &format!("{}/", name))?;
+            } else {
+                self.add_file(&name, fs::read(entry.path())?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the archive: header, entry data, then the 64-byte directory entries
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let data_size: u64 = self.entries.iter().map(|e| e.data.len() as u64).sum();
+        let dir_offset = 12 + data_size;
+        let dir_size = self.entries.len() as u64 * 64;
+        if dir_offset + dir_size > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PAK archive larger than 4GB",
+            ));
+        }
+
+        writer.write_all(b"PACK")?;
+        writer.write_u32::<LittleEndian>(dir_offset as u32)?;
+        writer.write_u32::<LittleEndian>(dir_size as u32)?;
+
+        for entry in &self.entries {
+            writer.write_all(&entry.data)?;
+        }
+
+        let mut file_offset = 12;
+        for entry in &self.entries {
+            writer.write_all(&entry.name)?;
+            writer.write_u32::<LittleEndian>(file_offset)?;
+            writer.write_u32::<LittleEndian>(entry.data.len() as u32)?;
+            file_offset += entry.data.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the archive bytes
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+        Ok(data)
+    }
+
+    /// Writes the archive to a file
+    pub fn save(&self, filepath: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+impl PakEntry {
+    fn name(&self) -> String {
+        let name_len = self.name.iter().position(|&b| b == 0).unwrap_or(56);
+        String::from_utf8_lossy(&self.name[..name_len]).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two entries laid out by id's packer, with junk left after the null of a name
+    fn small_pak() -> Vec<u8> {
+        let mut data = b"PACK".to_vec();
+        data.extend_from_slice(&(12u32 + 7).to_le_bytes());
+        data.extend_from_slice(&128u32.to_le_bytes());
+        data.extend_from_slice(b"abcdefg");
+        for (name, offset, size) in [(&b"gfx/a.lmp"[..], 12u32, 3u32), (b"maps/b.bsp", 15, 4)] {
+            let mut raw_name = [0xAAu8; 56];
+            raw_name[..name.len()].copy_from_slice(name);
+            raw_name[name.len()] = 0;
+            data.extend_from_slice(&raw_name);
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn round_trip_preserves_bytes() {
+        let data = small_pak();
+        let pak = Pak::from_bytes(data.clone()).unwrap();
+        assert_eq!(pak.find_file("MAPS/B.BSP").unwrap(), Some(&b"defg"[..]));
+        assert_eq!(PakWriter::from_pak(&pak).unwrap().to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn long_names_are_rejected() {
+        let mut writer = PakWriter::new();
+        writer.add_file(&"a".repeat(55), Vec::new()).unwrap();
+        let error = writer.add_file(&"b".repeat(56), Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,4 +1,4 @@
-pub use camera::Camera;
+pub use camera::{find_spawn_point, Camera};
 use glam::Vec3;
 use glam::Vec4Swizzles;
 use sdl2::event::Event;
@@ -10,13 +10,12 @@ use sdl2::rect::Point;
 use sdl2::Sdl;
 use sdl2::{pixels::Color, render::WindowCanvas};
 
-use crate::bsp::Edge;
-use crate::bsp::Face;
-use crate::bsp::Plane;
-use crate::bsp::Vertex;
-use crate::models::*;
 use crate::WIN_HEIGHT;
 use crate::WIN_WIDTH;
+use quake::bsp::Edge;
+use quake::bsp::Face;
+use quake::bsp::Vertex;
+use quake::models::*;
 
 mod camera;
 mod edges;
@@ -29,7 +28,7 @@ pub fn render(
     vertices: &Vec<Vertex>,
     edges: &[Edge],
     faces: &[Face],
-    planes: &[Plane],
+    models: &[(Vec3, &[u32])], // origin and faces of the world and each brush entity
     ledges: &[i32],
 ) {
     // Create an off-screen texture at the fixed resolution (320x200)
@@ -55,7 +54,19 @@ pub fn render(
     canvas.clear();
     let (window_width, window_height) = canvas.window().size();
 
-    render_faces(canvas, camera, faces, edges, vertices, ledges, Color::GRAY);
+    for (origin, model_faces) in models {
+        render_faces(
+            canvas,
+            camera,
+            faces,
+            model_faces,
+            *origin,
+            edges,
+            vertices,
+            ledges,
+            Color::GRAY,
+        );
+    }
     edges::render_edges(canvas, camera, vertices, edges, window_width, window_height);
 
     // Present the canvas to display the final output
@@ -237,16 +248,19 @@ pub fn get_color(seed: u32) -> Color {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_faces(
     canvas: &mut sdl2::render::WindowCanvas,
     camera: &Camera,
     faces: &[Face],
+    face_ids: &[u32],
+    origin: Vec3, // brush entities are moved by their origin
     edges: &[Edge],
     vertices: &[Vertex],
     ledges: &[i32],
     solid_color: Color,
 ) {
-    for face in faces {
+    for face in face_ids.iter().filter_map(|&id| faces.get(id as usize)) {
         let mut face_vertices = Vec::new();
 
         // Iterate through the edges for this face
@@ -260,11 +274,14 @@ pub fn render_faces(
             };
 
             // Add vertices based on the edge direction
-            if ledge_index >= 0 {
-                face_vertices.push(vertices[edge.start_vertex as usize].clone());
+            let vertex = if ledge_index >= 0 {
+                &vertices[edge.start_vertex as usize]
             } else {
-                face_vertices.push(vertices[edge.end_vertex as usize].clone());
-            }
+                &vertices[edge.end_vertex as usize]
+            };
+            face_vertices.push(Vertex {
+                coordinates: vertex.coordinates + origin,
+            });
         }
 
         if !is_face_in_view(camera, &face_vertices) {
@@ -1,6 +1,9 @@
 use glam::{Mat4, Vec3, Vec4Swizzles};
 
-use crate::bsp::Vertex;
+use quake::bsp::{Entity, Vertex};
+
+/// Eye height above the player origin (DEFAULT_VIEWHEIGHT)
+const VIEW_HEIGHT: f32 = 22.0;
 
 pub struct Camera {
     pub position: Vec3,
@@ -47,3 +50,50 @@ impl Camera {
         self.up = self.right.cross(self.forward).normalize(); // Up from right and forward
     }
 }
+
+/// Returns where the camera starts in a map: position, yaw and pitch in degrees.
+///
+/// Uses info_player_start, then info_player_deathmatch, then info_intermission, and
+/// falls back to the centre of the world bounding box looking toward Y.
+pub fn find_spawn_point(entities: &[Entity], vertices: &[Vertex]) -> (Vec3, f32, f32) {
+    for classname in ["info_player_start", "info_player_deathmatch"] {
+        if let Some((entity, origin)) = entities
+            .iter()
+            .filter(|entity| entity.classname() == classname)
+            .find_map(|entity| Some((entity, entity.origin()?)))
+        {
+            let angle = entity.angle().unwrap_or(0.0);
+            return (
+                origin + Vec3::new(0.0, 0.0, VIEW_HEIGHT),
+                quake_yaw(angle),
+                0.0,
+            );
+        }
+    }
+
+    if let Some((entity, origin)) = entities
+        .iter()
+        .filter(|entity| entity.classname() == "info_intermission")
+        .find_map(|entity| Some((entity, entity.origin()?)))
+    {
+        // "pitch yaw roll", Quake pitch is positive looking down
+        let mangle = entity.mangle().unwrap_or(Vec3::ZERO);
+        return (origin, quake_yaw(mangle.y), -mangle.x);
+    }
+
+    if vertices.is_empty() {
+        return (Vec3::ZERO, 0.0, 0.0);
+    }
+    let min = vertices
+        .iter()
+        .fold(Vec3::splat(f32::MAX), |min, v| min.min(v.coordinates));
+    let max = vertices
+        .iter()
+        .fold(Vec3::splat(f32::MIN), |max, v| max.max(v.coordinates));
+    ((min + max) * 0.5, 0.0, 0.0)
+}
+
+/// Quake angles start on X and turn toward Y, the camera yaw starts on Y and turns toward X
+fn quake_yaw(angle: f32) -> f32 {
+    90.0 - angle
+}
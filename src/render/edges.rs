@@ -2,9 +2,9 @@ use glam::Vec3;
 use glam::Vec4Swizzles;
 use sdl2::pixels::Color;
 
-use crate::bsp::Edge;
+use quake::bsp::Edge;
 
-use crate::bsp::Vertex;
+use quake::bsp::Vertex;
 
 use camera::Camera;
 
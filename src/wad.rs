@@ -1,13 +1,20 @@
 use std::io::{self, Read};
 
+pub use self::writer::WadWriter;
+
 use byteorder::{LittleEndian, ReadBytesExt};
 
+use crate::image::{IndexedImage, MipTex};
+use crate::palette;
+mod writer;
+
 pub struct Wad {
     pub data: Vec<u8>,
 }
 
 pub struct WadHeader {
-    pub name: String, // Must be "WAD2"
+    pub name: String, // Must be "WAD2", or "WAD3" for Half-Life
+    pub version: WadVersion,
     pub numentries: u32,
     pub diroffset: u32,
 }
@@ -15,12 +22,61 @@ pub struct WadHeader {
 #[derive(Debug)]
 pub struct WadAsset {
     pub offset: u32,
-    pub dsize: u32,        // Size of the entry in WAD file
-    pub size: u32,         // Size of the entry in memory
-    pub entry_type: char, // "@" = Raw bytes, "B" = Pictures (status bar), "D" = MIP Textures (3D brush models)
+    pub dsize: u32, // Size of the entry in WAD file
+    pub size: u32,  // Size of the entry in memory
+    pub entry_type: LumpType,
     pub compression: char, // 0 = no compression
-    pub dummy: u16,       // unused
-    pub name: String,     // Max 16 chars, null byte terminated
+    pub dummy: u16,        // unused
+    pub name: String,      // Max 16 chars, null byte terminated
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WadVersion {
+    Wad2, // Quake
+    Wad3, // Half-Life, images carry their own palette
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumpType {
+    Palette,        // "@" = Raw bytes, the 768 byte palette
+    QPic,           // "B" = Pictures (status bar), qpic with width and height
+    MipTex,         // "D" = MIP Textures (3D brush models)
+    PalettedMipTex, // "C" in WAD3 = MIP Textures followed by their palette
+    Font,           // "F" in WAD3 = qpic with glyph positions and palette
+    Other(char),    // Labels, sounds... not used by Quake itself
+}
+
+/// Decoded WAD lump
+#[derive(Debug)]
+pub enum Lump {
+    Palette(Vec<(u8, u8, u8)>),
+    Picture(IndexedImage),
+    MipTex(MipTex),
+}
+
+impl LumpType {
+    /// "C" is a sound in WAD2 but a miptex in WAD3
+    pub fn from_char(c: char, version: WadVersion) -> Self {
+        match (c, version) {
+            ('@', _) => LumpType::Palette,
+            ('B', _) => LumpType::QPic,
+            ('D', _) => LumpType::MipTex,
+            ('C', WadVersion::Wad3) => LumpType::PalettedMipTex,
+            ('F', WadVersion::Wad3) => LumpType::Font,
+            _ => LumpType::Other(c),
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            LumpType::Palette => '@',
+            LumpType::QPic => 'B',
+            LumpType::MipTex => 'D',
+            LumpType::PalettedMipTex => 'C',
+            LumpType::Font => 'F',
+            LumpType::Other(c) => c,
+        }
+    }
 }
 
 impl Wad {
@@ -28,25 +84,38 @@ impl Wad {
         Wad { data }
     }
 
-    pub fn read_header(&self) -> WadHeader {
+    pub fn read_header(&self) -> io::Result<WadHeader> {
         let mut cursor = io::Cursor::new(&self.data);
 
         // Read the first 4 bytes as a string
         let mut name_buf = [0u8; 4];
-        cursor.read_exact(&mut name_buf).unwrap();
+        cursor.read_exact(&mut name_buf)?;
         let name = String::from_utf8_lossy(&name_buf).to_string();
-        let numentries = cursor.read_u32::<LittleEndian>().unwrap();
-        let diroffset = cursor.read_u32::<LittleEndian>().unwrap();
 
-        WadHeader {
+        let version = match name.as_str() {
+            "WAD2" => WadVersion::Wad2,
+            "WAD3" => WadVersion::Wad3,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported WAD file {:?}", name),
+                ))
+            }
+        };
+
+        let numentries = cursor.read_u32::<LittleEndian>()?;
+        let diroffset = cursor.read_u32::<LittleEndian>()?;
+
+        Ok(WadHeader {
             name,
+            version,
             numentries,
             diroffset,
-        }
+        })
     }
 
-    pub fn read_directory(&self) -> Vec<WadAsset> {
-        let header = self.read_header();
+    pub fn read_directory(&self) -> io::Result<Vec<WadAsset>> {
+        let header = self.read_header()?;
 
         let file_number = header.numentries;
 
@@ -55,19 +124,18 @@ impl Wad {
         let mut cursor = io::Cursor::new(&self.data);
         cursor.set_position(header.diroffset.into());
         for _ in 0..file_number {
-            let offset = cursor.read_u32::<LittleEndian>().unwrap();
-            let dsize = cursor.read_u32::<LittleEndian>().unwrap();
-            let size = cursor.read_u32::<LittleEndian>().unwrap();
-            let entry_type = char::from(cursor.read_u8().unwrap());
-            let compression = char::from(cursor.read_u8().unwrap());
+            let offset = cursor.read_u32::<LittleEndian>()?;
+            let dsize = cursor.read_u32::<LittleEndian>()?;
+            let size = cursor.read_u32::<LittleEndian>()?;
+            let entry_type = LumpType::from_char(char::from(cursor.read_u8()?), header.version);
+            let compression = char::from(cursor.read_u8()?);
 
-            let dummy = cursor.read_u16::<LittleEndian>().unwrap(); // not used
+            let dummy = cursor.read_u16::<LittleEndian>()?; // not used
 
             let mut file_buf = [0u8; 16]; // 16 bytes
-            cursor.read_exact(&mut file_buf).unwrap();
-            let name = String::from_utf8_lossy(&file_buf)
-                .trim_matches(char::from(0))
-                .to_string();
+            cursor.read_exact(&mut file_buf)?;
+            let name_len = file_buf.iter().position(|&b| b == 0).unwrap_or(16);
+            let name = String::from_utf8_lossy(&file_buf[..name_len]).to_string();
 
             assets.push(WadAsset {
                 offset,
@@ -79,19 +147,81 @@ impl Wad {
                 name,
             });
         }
-        assets
+        Ok(assets)
+    }
+
+    /// Returns the raw bytes of a lump by name, None if the WAD does not have it
+    pub fn find_file(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.read_directory()?.iter().find(|file| file.name == path) {
+            Some(file) => Ok(Some(self.lump_data(file)?.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the bytes of a lump, checked against the file size
+    pub fn lump_data(&self, asset: &WadAsset) -> io::Result<&[u8]> {
+        if asset.compression != char::from(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("WAD lump {} is compressed", asset.name),
+            ));
+        }
+        let start = asset.offset as usize;
+        let end = start + asset.dsize as usize;
+        self.data.get(start..end).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("WAD lump {} lies outside the file", asset.name),
+            )
+        })
+    }
+
+    /// Decodes a lump by name, based on its type
+    pub fn read_lump(&self, name: &str) -> io::Result<Lump> {
+        let asset = self
+            .read_directory()?
+            .into_iter()
+            .find(|asset| asset.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("WAD lump {} not found", name),
+                )
+            })?;
+        self.decode(&asset)
     }
 
-    /// Returns file by path "maps/e1m1.bsp"
-    pub fn find_file(&self, path: &str) -> Option<Vec<u8>> {
-        let directory = self.read_directory();
-        for file in directory {
-            if file.name == path {
-                let start = file.offset as usize;
-                let end = start + file.dsize as usize;
-                return Some(self.data[start..end].to_vec());
+    /// Decodes a lump of the directory, based on its type
+    pub fn decode(&self, asset: &WadAsset) -> io::Result<Lump> {
+        let version = self.read_header()?.version;
+        let data = self.lump_data(asset)?;
+
+        // conchars is typed as a miptex but is a bare 128x128 picture
+        if asset.name.eq_ignore_ascii_case("conchars") {
+            return Ok(Lump::Picture(read_conchars(data)?));
+        }
+
+        match asset.entry_type {
+            LumpType::Palette => Ok(Lump::Palette(palette::convert_palette(data))),
+            LumpType::QPic if version == WadVersion::Wad3 => {
+                Ok(Lump::Picture(IndexedImage::from_qpic_with_palette(data)?))
             }
+            LumpType::QPic => Ok(Lump::Picture(IndexedImage::from_qpic(data)?)),
+            LumpType::MipTex => Ok(Lump::MipTex(MipTex::from_bytes(data)?)),
+            LumpType::PalettedMipTex => Ok(Lump::MipTex(MipTex::from_bytes_with_palette(data)?)),
+            LumpType::Font => Ok(Lump::Picture(IndexedImage::from_font(data)?)),
+            LumpType::Other(c) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("WAD lump {} has unsupported type {:?}", asset.name, c),
+            )),
         }
-        None
     }
 }
+
+/// The console font: 16x16 characters of 8x8 pixels, no header
+fn read_conchars(data: &[u8]) -> io::Result<IndexedImage> {
+    let pixels = data
+        .get(..128 * 128)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "conchars lump too small"))?;
+    IndexedImage::new(128, 128, pixels.to_vec())
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::LumpType;
+use crate::image::{IndexedImage, MipTex};
+
+/// Builds a "WAD2" file from named pictures, textures and palettes, or a Half-Life "WAD3"
+/// one as soon as it holds lumps only WAD3 has (paletted textures, fonts)
+#[derive(Default)]
+pub struct WadWriter {
+    lumps: Vec<WadLump>,
+}
+
+struct WadLump {
+    name: String,
+    entry_type: LumpType,
+    data: Vec<u8>,
+}
+
+impl WadWriter {
+    pub fn new() -> Self {
+        WadWriter { lumps: Vec::new() }
+    }
+
+    /// Adds raw lump data, names must fit in 15 bytes
+    pub fn add_lump(&mut self, name: &str, entry_type: LumpType, data: Vec<u8>) -> io::Result<()> {
+        if name.is_empty() || name.len() > 15 || name.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid WAD lump name {:?}", name),
+            ));
+        }
+        if self
+            .lumps
+            .iter()
+            .any(|lump| lump.name.eq_ignore_ascii_case(name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Duplicate WAD lump {}", name),
+            ));
+        }
+
+        self.lumps.push(WadLump {
+            name: name.to_string(),
+            entry_type,
+            data,
+        });
+        Ok(())
+    }
+
+    /// Adds a status bar style picture
+    pub fn add_qpic(&mut self, name: &str, image: &IndexedImage) -> io::Result<()> {
+        let mut data = Vec::with_capacity(8 + image.pixels.len());
+        data.write_u32::<LittleEndian>(image.width)?;
+        data.write_u32::<LittleEndian>(image.height)?;
+        data.extend_from_slice(&image.pixels);
+        self.add_lump(name, LumpType::QPic, data)
+    }
+
+    /// Adds a texture from its full size image, the smaller mip levels are generated
+    pub fn add_miptex(
+        &mut self,
+        name: &str,
+        image: &IndexedImage,
+        palette: &[(u8, u8, u8)],
+    ) -> io::Result<()> {
+        let miptex = MipTex::from_image(name, image, palette)?;
+        self.add_lump(name, LumpType::MipTex, miptex.to_bytes())
+    }
+
+    /// Adds a 256 colour palette
+    pub fn add_palette(&mut self, name: &str, palette: &[(u8, u8, u8)]) -> io::Result<()> {
+        let data = palette.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+        self.add_lump(name, LumpType::Palette, data)
+    }
+
+    /// Writes the file: header, lumps aligned on 4 bytes, then the 32-byte directory entries
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut offsets = Vec::with_capacity(self.lumps.len());
+        let mut offset = 12u64;
+        for lump in &self.lumps {
+            offsets.push(offset);
+            offset += padded_len(lump.data.len()) as u64;
+        }
+        if offset + self.lumps.len() as u64 * 32 > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "WAD file larger than 4GB",
+            ));
+        }
+
+        writer.write_all(self.magic())?;
+        writer.write_u32::<LittleEndian>(self.lumps.len() as u32)?;
+        writer.write_u32::<LittleEndian>(offset as u32)?;
+
+        for lump in &self.lumps {
+            writer.write_all(&lump.data)?;
+            writer.write_all(&[0u8; 3][..padded_len(lump.data.len()) - lump.data.len()])?;
+        }
+
+        for (lump, &offset) in self.lumps.iter().zip(&offsets) {
+            writer.write_u32::<LittleEndian>(offset as u32)?;
+            writer.write_u32::<LittleEndian>(lump.data.len() as u32)?; // dsize
+            writer.write_u32::<LittleEndian>(lump.data.len() as u32)?; // size
+            writer.write_u8(lump.entry_type.to_char() as u8)?;
+            writer.write_u8(0)?; // no compression
+            writer.write_u16::<LittleEndian>(0)?;
+            let mut name_buf = [0u8; 16];
+            name_buf[..lump.name.len()].copy_from_slice(lump.name.as_bytes());
+            writer.write_all(&name_buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// "C" is a sound in WAD2, so paletted textures and fonts need a WAD3
+    fn magic(&self) -> &'static [u8; 4] {
+        let wad3 = self
+            .lumps
+            .iter()
+            .any(|lump| matches!(lump.entry_type, LumpType::PalettedMipTex | LumpType::Font));
+        if wad3 {
+            b"WAD3"
+        } else {
+            b"WAD2"
+        }
+    }
+
+    /// Writes the WAD to a file
+    pub fn save(&self, filepath: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+fn padded_len(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::{Wad, WadVersion};
+
+    fn written(entry_type: LumpType) -> Wad {
+        let mut writer = WadWriter::new();
+        writer.add_lump("lump", entry_type, vec![0; 5]).unwrap();
+        let mut data = Vec::new();
+        writer.write(&mut data).unwrap();
+        Wad::new(data)
+    }
+
+    #[test]
+    fn paletted_textures_make_a_wad3() {
+        let wad = written(LumpType::MipTex);
+        assert_eq!(wad.read_header().unwrap().version, WadVersion::Wad2);
+
+        let wad = written(LumpType::PalettedMipTex);
+        assert_eq!(wad.read_header().unwrap().version, WadVersion::Wad3);
+        let directory = wad.read_directory().unwrap();
+        assert_eq!(directory[0].entry_type, LumpType::PalettedMipTex);
+        assert_eq!(wad.find_file("lump").unwrap(), Some(vec![0; 5]));
+    }
+}